@@ -0,0 +1,289 @@
+use std::borrow::Cow;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+
+/// Compression formats understood when reading and writing elevdumps.
+///
+/// Reading detects the format from the data's magic bytes, while writing picks
+/// it from the output file's extension. Each format needs its cargo feature
+/// (`gzip` or `zstd`) to actually be decoded or encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// Bytes needed to tell every format apart
+const MAGIC_LEN: usize = ZSTD_MAGIC.len();
+
+impl Compression {
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if data.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// Decompresses `data` according to its magic bytes, borrowing it
+    /// unchanged when it is not compressed.
+    pub fn decompress(data: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+        match Self::detect(data) {
+            Compression::None => Ok(Cow::Borrowed(data)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut out = Vec::new();
+                flate2::read::MultiGzDecoder::new(data).read_to_end(&mut out)?;
+                Ok(Cow::Owned(out))
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Cow::Owned(zstd::stream::decode_all(data)?)),
+            #[allow(unreachable_patterns)]
+            compression => Err(unsupported(compression)),
+        }
+    }
+
+    /// Wraps `reader` in a streaming decoder, detecting the format from the
+    /// first bytes it yields.
+    pub fn decoder<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+        // Pipes may yield fewer bytes at a time than the longest magic number,
+        // so read until it is whole and put the bytes back in front of the rest
+        let mut magic = Vec::with_capacity(MAGIC_LEN);
+        while magic.len() < MAGIC_LEN {
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if available.is_empty() {
+                break;
+            }
+            let len = available.len().min(MAGIC_LEN - magic.len());
+            magic.extend_from_slice(&available[..len]);
+            reader.consume(len);
+        }
+        let compression = Self::detect(&magic);
+        let reader = io::Cursor::new(magic).chain(reader);
+
+        match compression {
+            Compression::None => Ok(Box::new(reader)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Box::new(io::BufReader::new(
+                flate2::bufread::MultiGzDecoder::new(reader),
+            ))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Box::new(io::BufReader::new(
+                zstd::stream::read::Decoder::with_buffer(reader)?,
+            ))),
+            #[allow(unreachable_patterns)]
+            compression => Err(unsupported(compression)),
+        }
+    }
+
+    /// Wraps `writer` in an encoder for this format.
+    pub fn encoder<W: Write>(self, writer: W) -> io::Result<CompressedWriter<W>> {
+        let inner = match self {
+            Compression::None => Encoder::None(writer),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(writer, 0)?),
+            #[allow(unreachable_patterns)]
+            compression => return Err(unsupported(compression)),
+        };
+        Ok(CompressedWriter { inner })
+    }
+}
+
+/// A writer that compresses everything written to it. [`CompressedWriter::finish`]
+/// must be called to write the end of the compressed stream.
+pub struct CompressedWriter<W: Write> {
+    inner: Encoder<W>,
+}
+
+enum Encoder<W: Write> {
+    None(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    pub fn finish(self) -> io::Result<W> {
+        match self.inner {
+            Encoder::None(mut writer) => {
+                writer.flush()?;
+                Ok(writer)
+            }
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            Encoder::None(writer) => writer.write(buf),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            Encoder::None(writer) => writer.flush(),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+fn unsupported(compression: Compression) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "{} compression requires the '{}' feature of the elev crate",
+            compression.name(),
+            compression.name()
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &[u8] = b"elevdump version 2\n0 0 0 0 1 1 1 5 7\n";
+
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        let mut writer = compression.encoder(Vec::new()).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn detects_magic_bytes() {
+        assert_eq!(Compression::detect(&[0x1f, 0x8b, 8, 0]), Compression::Gzip);
+        assert_eq!(
+            Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0]),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::detect(TEXT), Compression::None);
+        assert_eq!(Compression::detect(&[0x28, 0xb5]), Compression::None);
+        assert_eq!(Compression::detect(&[]), Compression::None);
+    }
+
+    #[test]
+    fn picks_the_format_from_the_extension() {
+        assert_eq!(Compression::from_path("a/dump.txt.gz"), Compression::Gzip);
+        assert_eq!(Compression::from_path("dump.zst"), Compression::Zstd);
+        assert_eq!(Compression::from_path("dump.txt"), Compression::None);
+        assert_eq!(Compression::from_path("gz"), Compression::None);
+    }
+
+    #[test]
+    fn leaves_plain_text_alone() {
+        assert_eq!(compress(Compression::None, TEXT), TEXT);
+        assert!(matches!(
+            Compression::decompress(TEXT).unwrap(),
+            Cow::Borrowed(TEXT)
+        ));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_round_trip() {
+        let compressed = compress(Compression::Gzip, TEXT);
+        assert_eq!(Compression::detect(&compressed), Compression::Gzip);
+        assert_eq!(&*Compression::decompress(&compressed).unwrap(), TEXT);
+
+        // Concatenated members decompress as one stream, as with gzip -d
+        let twice = [compressed.clone(), compressed].concat();
+        assert_eq!(
+            &*Compression::decompress(&twice).unwrap(),
+            [TEXT, TEXT].concat()
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trip() {
+        let compressed = compress(Compression::Zstd, TEXT);
+        assert_eq!(Compression::detect(&compressed), Compression::Zstd);
+        assert_eq!(&*Compression::decompress(&compressed).unwrap(), TEXT);
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn reports_missing_features() {
+        let error = Compression::decompress(&[0x1f, 0x8b, 8, 0]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert!(error.to_string().contains("'gzip' feature"));
+    }
+
+    /// Yields its data a byte at a time, as a slow pipe might
+    fn trickle(data: &[u8]) -> io::BufReader<&[u8]> {
+        io::BufReader::with_capacity(1, data)
+    }
+
+    fn decode(reader: impl BufRead) -> Vec<u8> {
+        let mut out = Vec::new();
+        Compression::decoder(reader)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn decodes_plain_text_read_a_byte_at_a_time() {
+        assert_eq!(decode(trickle(TEXT)), TEXT);
+        assert_eq!(decode(trickle(b"el")), b"el");
+        assert_eq!(decode(trickle(b"")), b"");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decodes_gzip_read_a_byte_at_a_time() {
+        assert_eq!(decode(trickle(&compress(Compression::Gzip, TEXT))), TEXT);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn decodes_zstd_read_a_byte_at_a_time() {
+        assert_eq!(decode(trickle(&compress(Compression::Zstd, TEXT))), TEXT);
+    }
+}
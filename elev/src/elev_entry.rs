@@ -1,5 +1,7 @@
 use std::fmt;
 use std::num::ParseIntError;
+use std::ops::Range;
+use std::str::FromStr;
 
 #[derive(Debug)]
 pub enum ElevEntryError {
@@ -27,6 +29,7 @@ impl fmt::Display for ElevEntryError {
 impl std::error::Error for ElevEntryError {}
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ElevEntry {
     pub page_x: i32,
     pub page_z: i32,
@@ -39,71 +42,25 @@ pub struct ElevEntry {
 
 impl ElevEntry {
     pub fn from_line(line: impl AsRef<str>) -> Result<Self, ElevEntryError> {
-        let line = line.as_ref();
-        let mut parts = line.split_whitespace();
+        Self::from_bytes(line.as_ref().as_bytes())
+    }
 
-        let mut parse_field = |field: &'static str| -> Result<&str, ElevEntryError> {
-            parts.next().ok_or(ElevEntryError::MissingField(field))
-        };
+    /// Parses a line given as bytes, so that a whole elevdump doesn't need
+    /// to be checked as UTF-8 first. Fields that aren't integers fail to
+    /// parse whatever bytes they hold.
+    pub(crate) fn from_bytes(line: &[u8]) -> Result<Self, ElevEntryError> {
+        let mut fields = Fields { rest: line };
 
-        let page_x = parse_field("page_x")?
-            .parse::<i32>()
-            .map_err(|e| ElevEntryError::ParseIntError("page_x", e))?;
-        let page_z = parse_field("page_z")?
-            .parse::<i32>()
-            .map_err(|e| ElevEntryError::ParseIntError("page_z", e))?;
-        let node_x = parse_field("node_x")?
-            .parse::<u8>()
-            .map_err(|e| ElevEntryError::ParseIntError("node_x", e))?;
-        let node_z = parse_field("node_z")?
-            .parse::<u8>()
-            .map_err(|e| ElevEntryError::ParseIntError("node_z", e))?;
-        let node_radius = parse_field("node_radius")?
-            .parse::<u8>()
-            .map_err(|e| ElevEntryError::ParseIntError("node_radius", e))?;
-
-        let texture_count = parse_field("texture_count")?
-            .parse::<usize>()
-            .map_err(|e| ElevEntryError::ParseIntError("texture_count", e))?;
-        let height_count = parse_field("height_count")?
-            .parse::<usize>()
-            .map_err(|e| ElevEntryError::ParseIntError("height_count", e))?;
-
-        let texture_ids: Result<Vec<u32>, ElevEntryError> = (0..texture_count)
-            .map(|_| {
-                parse_field("texture_id").and_then(|s| {
-                    s.parse::<u32>()
-                        .map_err(|e| ElevEntryError::ParseIntError("texture_id", e))
-                })
-            })
-            .collect();
-        let texture_ids = texture_ids?;
-
-        let heights: Result<Vec<i32>, ElevEntryError> = (0..height_count)
-            .map(|_| {
-                parse_field("height").and_then(|s| {
-                    s.parse::<i32>()
-                        .map_err(|e| ElevEntryError::ParseIntError("height", e))
-                })
-            })
-            .collect();
-        let heights = heights?;
-
-        if texture_ids.len() != texture_count {
-            return Err(ElevEntryError::InvalidCount(
-                "texture",
-                texture_count,
-                texture_ids.len(),
-            ));
-        }
+        let page_x = fields.parse("page_x")?;
+        let page_z = fields.parse("page_z")?;
+        let node_x = fields.parse("node_x")?;
+        let node_z = fields.parse("node_z")?;
+        let node_radius = fields.parse("node_radius")?;
+        let texture_count: usize = fields.parse("texture_count")?;
+        let height_count: usize = fields.parse("height_count")?;
 
-        if heights.len() != height_count {
-            return Err(ElevEntryError::InvalidCount(
-                "height",
-                height_count,
-                heights.len(),
-            ));
-        }
+        let texture_ids = fields.parse_values("texture", "texture_id", texture_count)?;
+        let heights = fields.parse_values("height", "height", height_count)?;
 
         Ok(Self {
             page_x,
@@ -115,4 +72,185 @@ impl ElevEntry {
             heights,
         })
     }
+
+    /// The world cells the entry sets, as ranges of world x and z. Cells past
+    /// the edge of its page are left out, as [`ElevMap`](crate::ElevMap)
+    /// ignores them.
+    pub fn world_cells(&self) -> (Range<i32>, Range<i32>) {
+        let span = |page: i32, node: u8| {
+            let start = i32::from(node).min(128);
+            let end = (start + 2 * i32::from(self.node_radius)).min(128);
+            page * 128 + start..page * 128 + end
+        };
+        (
+            span(self.page_x, self.node_x),
+            span(self.page_z, self.node_z),
+        )
+    }
+}
+
+/// The whitespace separated fields of a line, parsed in place
+struct Fields<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let start = self.rest.iter().position(|b| !b.is_ascii_whitespace())?;
+        let rest = &self.rest[start..];
+        let end = rest
+            .iter()
+            .position(u8::is_ascii_whitespace)
+            .unwrap_or(rest.len());
+        self.rest = &rest[end..];
+        Some(&rest[..end])
+    }
+
+    fn parse<T>(&mut self, field: &'static str) -> Result<T, ElevEntryError>
+    where
+        T: TryFrom<i64> + FromStr<Err = ParseIntError>,
+    {
+        let token = self.next().ok_or(ElevEntryError::MissingField(field))?;
+        parse_int(token, field)
+    }
+
+    /// Parses `count` values, reporting how many there were when the line
+    /// ends before all of them
+    fn parse_values<T>(
+        &mut self,
+        kind: &'static str,
+        field: &'static str,
+        count: usize,
+    ) -> Result<Vec<T>, ElevEntryError>
+    where
+        T: TryFrom<i64> + FromStr<Err = ParseIntError>,
+    {
+        // Every value takes at least two bytes including its separator, which
+        // bounds the allocation when a line claims an absurd count.
+        let mut values = Vec::with_capacity(count.min(self.rest.len() / 2 + 1));
+        for _ in 0..count {
+            let Some(token) = self.next() else {
+                return Err(ElevEntryError::InvalidCount(kind, count, values.len()));
+            };
+            values.push(parse_int(token, field)?);
+        }
+        Ok(values)
+    }
+}
+
+/// Parses plain decimal integers directly, leaving anything unusual to
+/// `str::parse` so that it is handled and reported the same way
+fn parse_int<T>(token: &[u8], field: &'static str) -> Result<T, ElevEntryError>
+where
+    T: TryFrom<i64> + FromStr<Err = ParseIntError>,
+{
+    let (negative, digits) = match token {
+        [b'-', digits @ ..] => (true, digits),
+        [b'+', digits @ ..] => (false, digits),
+        digits => (false, digits),
+    };
+    // Eighteen digits always fit in an i64
+    if !digits.is_empty() && digits.len() <= 18 && digits.iter().all(u8::is_ascii_digit) {
+        let value = digits
+            .iter()
+            .fold(0i64, |value, digit| value * 10 + i64::from(digit - b'0'));
+        if let Ok(value) = T::try_from(if negative { -value } else { value }) {
+            return Ok(value);
+        }
+    }
+
+    String::from_utf8_lossy(token)
+        .parse()
+        .map_err(|e| ElevEntryError::ParseIntError(field, e))
+}
+
+/// Formats the entry as a single elevdump line, without the trailing newline.
+impl fmt::Display for ElevEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} {}",
+            self.page_x,
+            self.page_z,
+            self.node_x,
+            self.node_z,
+            self.node_radius,
+            self.texture_ids.len(),
+            self.heights.len()
+        )?;
+        for texture_id in &self.texture_ids {
+            write!(f, " {texture_id}")?;
+        }
+        for height in &self.heights {
+            write!(f, " {height}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats_a_line() {
+        let line = "-3 12 10 20 2 1 4 32778 -5 0 +7 2147483647";
+        let entry = ElevEntry::from_line(line).unwrap();
+
+        assert_eq!((entry.page_x, entry.page_z), (-3, 12));
+        assert_eq!((entry.node_x, entry.node_z, entry.node_radius), (10, 20, 2));
+        assert_eq!(entry.texture_ids, vec![32778]);
+        assert_eq!(entry.heights, vec![-5, 0, 7, i32::MAX]);
+        assert_eq!(
+            entry.to_string(),
+            "-3 12 10 20 2 1 4 32778 -5 0 7 2147483647"
+        );
+    }
+
+    #[test]
+    fn reports_malformed_fields() {
+        assert!(matches!(
+            ElevEntry::from_line("0 0 0 0"),
+            Err(ElevEntryError::MissingField("node_radius"))
+        ));
+        assert!(matches!(
+            ElevEntry::from_line("0 0 128 0 1 1 1 5 0"),
+            Ok(ElevEntry { node_x: 128, .. })
+        ));
+        assert!(matches!(
+            ElevEntry::from_line("0 0 256 0 1 1 1 5 0"),
+            Err(ElevEntryError::ParseIntError("node_x", _))
+        ));
+        assert!(matches!(
+            ElevEntry::from_line("0 0 0 0 1 1 1 5 12a"),
+            Err(ElevEntryError::ParseIntError("height", _))
+        ));
+        assert!(matches!(
+            ElevEntry::from_line("0 0 0 0 1 1 1 5 99999999999999999999"),
+            Err(ElevEntryError::ParseIntError("height", _))
+        ));
+    }
+
+    #[test]
+    fn reports_too_few_values() {
+        assert!(matches!(
+            ElevEntry::from_line("0 0 0 0 1 3 1 5 6"),
+            Err(ElevEntryError::InvalidCount("texture", 3, 2))
+        ));
+        assert!(matches!(
+            ElevEntry::from_line("0 0 0 0 1 1 4 5 10 20"),
+            Err(ElevEntryError::InvalidCount("height", 4, 2))
+        ));
+        // A huge count is reported rather than allocated for
+        assert!(matches!(
+            ElevEntry::from_line("0 0 0 0 1 1 18446744073709551615 5 10"),
+            Err(ElevEntryError::InvalidCount("height", usize::MAX, 1))
+        ));
+    }
+
+    #[test]
+    fn world_cells_stop_at_the_page_edge() {
+        let entry = ElevEntry::from_line("-1 2 120 0 8 1 1 5 0").unwrap();
+        assert_eq!(entry.world_cells(), (-8..0, 256..272));
+    }
 }
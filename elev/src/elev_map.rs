@@ -1,15 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
 use super::{ElevDump, ElevEntry};
 
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ElevCell {
     pub texture_id: u32,
     pub rotation: Rotation,
     pub height: i32,
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Rotation {
     #[default]
     R0,
@@ -18,9 +20,34 @@ pub enum Rotation {
     R3,
 }
 
+impl Rotation {
+    /// Index of the rotation, 0 for R0 through 3 for R3
+    pub fn index(self) -> u8 {
+        match self {
+            Rotation::R0 => 0,
+            Rotation::R1 => 1,
+            Rotation::R2 => 2,
+            Rotation::R3 => 3,
+        }
+    }
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0 => Some(Rotation::R0),
+            1 => Some(Rotation::R1),
+            2 => Some(Rotation::R2),
+            3 => Some(Rotation::R3),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ElevPage {
     cells: [[ElevCell; 128]; 128],
+    /// Which cells have been set, one row of bits per z, as cells that no
+    /// entry covers still hold flat defaults
+    defined: [u128; 128],
 }
 
 impl ElevPage {
@@ -31,22 +58,28 @@ impl ElevPage {
                 height: 0,
                 rotation: Default::default(),
             }; 128]; 128],
+            defined: [0; 128],
         }
     }
 
     pub fn get_cell(&self, x: u8, z: u8) -> Option<&ElevCell> {
-        let Some(z_cells) = self.cells.get(usize::from(z)) else {
-            return None;
-        };
-
-        let Some(zx_cell) = z_cells.get(usize::from(x)) else {
-            return None;
-        };
+        let z_cells = self.cells.get(usize::from(z))?;
+        let zx_cell = z_cells.get(usize::from(x))?;
 
         Some(zx_cell)
     }
 
-    fn set_cell(&mut self, x: u8, z: u8, cell: ElevCell) {
+    /// Iterates over every cell of the page as `(x, z, cell)`
+    pub fn iter_cells(&self) -> impl Iterator<Item = (u8, u8, &ElevCell)> {
+        self.cells.iter().enumerate().flat_map(|(z, z_cells)| {
+            z_cells
+                .iter()
+                .enumerate()
+                .map(move |(x, cell)| (x as u8, z as u8, cell))
+        })
+    }
+
+    pub fn set_cell(&mut self, x: u8, z: u8, cell: ElevCell) {
         let Some(z_cells) = self.cells.get_mut(usize::from(z)) else {
             return;
         };
@@ -56,28 +89,76 @@ impl ElevPage {
         };
 
         *zx_cell = cell;
+        self.defined[usize::from(z)] |= 1 << x;
+    }
+
+    /// Whether the cell was set, rather than left flat because no entry of
+    /// the page covers it
+    pub fn is_defined(&self, x: u8, z: u8) -> bool {
+        self.defined
+            .get(usize::from(z))
+            .is_some_and(|row| x < 128 && row & (1 << x) != 0)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ElevMap {
     pages: HashMap<(i32, i32), ElevPage>,
 }
 
 impl ElevMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_page(&self, page_x: i32, page_z: i32) -> Option<&ElevPage> {
+        self.pages.get(&(page_x, page_z))
+    }
+
     pub fn get_cell(&self, page_x: i32, page_z: i32, x: u8, z: u8) -> Option<&ElevCell> {
-        self.pages
-            .get(&(page_x, page_z))
+        self.get_page(page_x, page_z)
             .and_then(|page| page.get_cell(x, z))
     }
 
-    fn set_cell(&mut self, page_x: i32, page_z: i32, x: u8, z: u8, cell: ElevCell) {
+    /// Looks up a cell by its world cell coordinates, `page * 128 + cell`
+    pub fn get_world_cell(&self, world_x: i32, world_z: i32) -> Option<&ElevCell> {
+        self.get_cell(
+            world_x.div_euclid(128),
+            world_z.div_euclid(128),
+            world_x.rem_euclid(128) as u8,
+            world_z.rem_euclid(128) as u8,
+        )
+    }
+
+    /// Sets a cell, creating its page filled with flat cells if needed
+    pub fn set_cell(&mut self, page_x: i32, page_z: i32, x: u8, z: u8, cell: ElevCell) {
         self.pages
             .entry((page_x, page_z))
             .or_insert_with(ElevPage::new)
             .set_cell(x, z, cell);
     }
 
+    /// Whether the cell at world cell coordinates `(world_x, world_z)` is on a
+    /// page of the map and was set by an entry
+    pub fn is_world_cell_defined(&self, world_x: i32, world_z: i32) -> bool {
+        self.pages
+            .get(&(world_x.div_euclid(128), world_z.div_euclid(128)))
+            .is_some_and(|page| {
+                page.is_defined(world_x.rem_euclid(128) as u8, world_z.rem_euclid(128) as u8)
+            })
+    }
+
+    /// Sets a cell by its world cell coordinates, `page * 128 + cell`
+    pub fn set_world_cell(&mut self, world_x: i32, world_z: i32, cell: ElevCell) {
+        self.set_cell(
+            world_x.div_euclid(128),
+            world_z.div_euclid(128),
+            world_x.rem_euclid(128) as u8,
+            world_z.rem_euclid(128) as u8,
+            cell,
+        );
+    }
+
     pub fn page_count(&self) -> usize {
         self.pages.len()
     }
@@ -101,13 +182,22 @@ impl ElevMap {
 
         (min_x, min_z, max_x, max_z)
     }
+
+    /// Every texture id used by at least one cell
+    pub fn texture_ids(&self) -> BTreeSet<u32> {
+        self.pages
+            .values()
+            .flat_map(|page| page.iter_cells().map(|(_, _, cell)| cell.texture_id))
+            .collect()
+    }
 }
 
+/// Applies the entries in order, so that where entries overlap, whether on
+/// the same page coordinates or not, the later one sets the cells as it does
+/// in the world
 impl From<&ElevDump> for ElevMap {
     fn from(dump: &ElevDump) -> Self {
-        let mut map = ElevMap {
-            pages: HashMap::new(),
-        };
+        let mut map = ElevMap::new();
 
         for entry in &dump.entries {
             map.apply_entry(entry);
@@ -135,7 +225,7 @@ impl ElevMap {
                     let mut texture_id = *entry
                         .texture_ids
                         .get(index)
-                        .unwrap_or(entry.texture_ids.get(0).unwrap_or(&0));
+                        .unwrap_or(entry.texture_ids.first().unwrap_or(&0));
 
                     let rotation = match texture_id & 0b1100_0000_0000_0000 {
                         0x8000 => Rotation::R0,
@@ -144,12 +234,12 @@ impl ElevMap {
                         0xC000 => Rotation::R3,
                         _ => panic!("impossible rotation"),
                     };
-                    texture_id = texture_id & (!0b1100_0000_0000_0000);
+                    texture_id &= !0b1100_0000_0000_0000;
 
                     let &height = entry
                         .heights
                         .get(index)
-                        .unwrap_or(entry.heights.get(0).unwrap_or(&0));
+                        .unwrap_or(entry.heights.first().unwrap_or(&0));
 
                     self.set_cell(
                         entry.page_x,
@@ -167,3 +257,372 @@ impl ElevMap {
         }
     }
 }
+
+/// Encodes the map as entries covering each page with a quadtree of squares,
+/// where a square whose cells all share a texture or a height stores that
+/// value once, and a square is split into quarters only when that takes fewer
+/// values to write. Squares without any defined cell are left out, while
+/// undefined cells among defined ones are written with their flat defaults.
+impl From<&ElevMap> for ElevDump {
+    fn from(map: &ElevMap) -> Self {
+        let mut keys: Vec<_> = map.pages.keys().copied().collect();
+        keys.sort_by_key(|&(page_x, page_z)| (page_z, page_x));
+
+        let mut entries = Vec::new();
+        for (page_x, page_z) in keys {
+            let page = &map.pages[&(page_x, page_z)];
+            entries.extend(
+                encode_square(page, 0, 0, 128)
+                    .into_iter()
+                    .map(|square| square.into_entry(page_x, page_z)),
+            );
+        }
+
+        ElevDump { entries }
+    }
+}
+
+/// An entry of a page being encoded, before its page is known
+struct EncodedSquare {
+    x: u8,
+    z: u8,
+    size: u8,
+    texture_ids: Vec<u32>,
+    heights: Vec<i32>,
+}
+
+impl EncodedSquare {
+    /// Number of fields the square takes in an elevdump line
+    fn cost(&self) -> usize {
+        7 + self.texture_ids.len() + self.heights.len()
+    }
+
+    fn into_entry(self, page_x: i32, page_z: i32) -> ElevEntry {
+        ElevEntry {
+            page_x,
+            page_z,
+            node_x: self.x,
+            node_z: self.z,
+            node_radius: self.size / 2,
+            texture_ids: self.texture_ids,
+            heights: self.heights,
+        }
+    }
+}
+
+fn encode_square(page: &ElevPage, x: u8, z: u8, size: u8) -> Vec<EncodedSquare> {
+    let any_defined = (z..z + size).any(|cell_z| {
+        let row = page.defined[usize::from(cell_z)];
+        let mask = (u128::MAX >> (128 - u32::from(size))) << x;
+        row & mask != 0
+    });
+    if !any_defined {
+        return Vec::new();
+    }
+
+    let mut texture_ids = Vec::with_capacity(usize::from(size) * usize::from(size));
+    let mut heights = Vec::with_capacity(texture_ids.capacity());
+    for dz in 0..size {
+        for dx in 0..size {
+            let cell = &page.cells[usize::from(z + dz)][usize::from(x + dx)];
+            texture_ids.push(encode_texture_id(cell));
+            heights.push(cell.height);
+        }
+    }
+
+    collapse_uniform(&mut texture_ids);
+    collapse_uniform(&mut heights);
+
+    let whole = EncodedSquare {
+        x,
+        z,
+        size,
+        texture_ids,
+        heights,
+    };
+    // Entries cover squares of twice their radius, so two cells is the least
+    if size == 2 || whole.cost() == 9 {
+        return vec![whole];
+    }
+
+    let half = size / 2;
+    let quarters: Vec<_> = [(0, 0), (half, 0), (0, half), (half, half)]
+        .into_iter()
+        .flat_map(|(dx, dz)| encode_square(page, x + dx, z + dz, half))
+        .collect();
+
+    if quarters.iter().map(EncodedSquare::cost).sum::<usize>() < whole.cost() {
+        quarters
+    } else {
+        vec![whole]
+    }
+}
+
+/// Keeps a single value when they are all the same
+fn collapse_uniform<T: PartialEq>(values: &mut Vec<T>) {
+    if values.windows(2).all(|pair| pair[0] == pair[1]) {
+        values.truncate(1);
+    }
+}
+
+/// The texture id of a cell with its rotation in the top two bits, as stored
+/// in elevdumps
+fn encode_texture_id(cell: &ElevCell) -> u32 {
+    let rotation_bits = match cell.rotation {
+        Rotation::R0 => 0x8000,
+        Rotation::R1 => 0x4000,
+        Rotation::R2 => 0x0000,
+        Rotation::R3 => 0xC000,
+    };
+    (cell.texture_id & !0b1100_0000_0000_0000) | rotation_bits
+}
+
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    use super::{ElevCell, ElevMap, ElevPage, Rotation};
+
+    /// Pages are stored as flat row-major (z, then x) arrays rather than as
+    /// 16384 individual cell objects.
+    #[derive(Serialize, Deserialize)]
+    struct PageRepr {
+        heights: Vec<i32>,
+        texture_ids: Vec<u32>,
+        rotations: Vec<u8>,
+        /// Left out when every cell is defined
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        defined: Option<Vec<bool>>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct MapPageRepr {
+        page_x: i32,
+        page_z: i32,
+        #[serde(flatten)]
+        page: PageRepr,
+    }
+
+    impl From<&ElevPage> for PageRepr {
+        fn from(page: &ElevPage) -> Self {
+            let cells = page.cells.iter().flatten();
+            PageRepr {
+                heights: cells.clone().map(|cell| cell.height).collect(),
+                texture_ids: cells.clone().map(|cell| cell.texture_id).collect(),
+                rotations: cells.map(|cell| cell.rotation.index()).collect(),
+                defined: (page.defined != [u128::MAX; 128]).then(|| {
+                    (0..128)
+                        .flat_map(|z| (0..128).map(move |x| page.is_defined(x, z)))
+                        .collect()
+                }),
+            }
+        }
+    }
+
+    impl TryFrom<PageRepr> for ElevPage {
+        type Error = String;
+
+        fn try_from(repr: PageRepr) -> Result<Self, Self::Error> {
+            const CELL_COUNT: usize = 128 * 128;
+
+            for (field, len) in [
+                ("heights", repr.heights.len()),
+                ("texture_ids", repr.texture_ids.len()),
+                ("rotations", repr.rotations.len()),
+                (
+                    "defined",
+                    repr.defined.as_ref().map_or(CELL_COUNT, Vec::len),
+                ),
+            ] {
+                if len != CELL_COUNT {
+                    return Err(format!(
+                        "Invalid {field} count: expected {CELL_COUNT}, got {len}"
+                    ));
+                }
+            }
+
+            let mut page = ElevPage::new();
+            for (i, cell) in page.cells.iter_mut().flatten().enumerate() {
+                let rotation = Rotation::from_index(repr.rotations[i])
+                    .ok_or_else(|| format!("Invalid rotation: {}", repr.rotations[i]))?;
+                *cell = ElevCell {
+                    texture_id: repr.texture_ids[i],
+                    rotation,
+                    height: repr.heights[i],
+                };
+            }
+            for (i, row) in page.defined.iter_mut().enumerate() {
+                *row = match &repr.defined {
+                    Some(defined) => (0..128)
+                        .filter(|&x| defined[i * 128 + x])
+                        .fold(0, |row, x| row | 1 << x),
+                    None => u128::MAX,
+                };
+            }
+
+            Ok(page)
+        }
+    }
+
+    impl Serialize for ElevPage {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            PageRepr::from(self).serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for ElevPage {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            ElevPage::try_from(PageRepr::deserialize(deserializer)?).map_err(D::Error::custom)
+        }
+    }
+
+    /// Maps are stored as a list of pages, since page coordinates cannot be
+    /// used as keys in formats such as JSON.
+    impl Serialize for ElevMap {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut keys: Vec<_> = self.pages.keys().copied().collect();
+            keys.sort_by_key(|&(page_x, page_z)| (page_z, page_x));
+
+            serializer.collect_seq(keys.into_iter().map(|(page_x, page_z)| MapPageRepr {
+                page_x,
+                page_z,
+                page: PageRepr::from(&self.pages[&(page_x, page_z)]),
+            }))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for ElevMap {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let mut pages = HashMap::new();
+            for repr in Vec::<MapPageRepr>::deserialize(deserializer)? {
+                let key = (repr.page_x, repr.page_z);
+                if pages.contains_key(&key) {
+                    return Err(D::Error::custom(format!(
+                        "Duplicate page {}, {}",
+                        key.0, key.1
+                    )));
+                }
+                let page = ElevPage::try_from(repr.page).map_err(D::Error::custom)?;
+                pages.insert(key, page);
+            }
+
+            Ok(ElevMap { pages })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "serde")]
+    fn map_of(dump: &str) -> ElevMap {
+        ElevMap::from(&ElevDump::from_str(dump).unwrap())
+    }
+
+    #[test]
+    fn encodes_maps_as_dumps_that_decode_to_the_same_cells() {
+        let rotations = [Rotation::R0, Rotation::R1, Rotation::R2, Rotation::R3];
+        let mut seed = 12345u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            seed >> 16
+        };
+
+        let mut map = ElevMap::new();
+        for world_z in 0..128 {
+            for world_x in 0..128 {
+                // Flat and uniform to the north, noisy in the south east
+                let cell = if world_z < 64 {
+                    ElevCell {
+                        texture_id: 3,
+                        rotation: Rotation::R0,
+                        height: 250,
+                    }
+                } else if world_x < 64 {
+                    ElevCell {
+                        texture_id: 4,
+                        rotation: Rotation::R2,
+                        height: world_x * 10 - world_z,
+                    }
+                } else {
+                    ElevCell {
+                        texture_id: random() % 1024,
+                        rotation: rotations[random() as usize % 4],
+                        height: random() as i32 % 2000 - 1000,
+                    }
+                };
+                map.set_world_cell(world_x, world_z, cell);
+            }
+        }
+        // A page only partly covered
+        for (world_x, world_z) in [(-128, 256), (-127, 256), (-3, 300), (-1, 383)] {
+            let cell = ElevCell {
+                texture_id: 9,
+                rotation: Rotation::R3,
+                height: world_x,
+            };
+            map.set_world_cell(world_x, world_z, cell);
+        }
+
+        let dump = ElevDump::from(&map);
+        let mut written = Vec::new();
+        dump.write_to(&mut written).unwrap();
+        let restored = ElevMap::from(&ElevDump::from_bytes(&written).unwrap());
+
+        assert_eq!(restored.page_count(), map.page_count());
+        for (&(page_x, page_z), page) in map.iter_pages() {
+            let other = restored.get_page(page_x, page_z).unwrap();
+            for (x, z, cell) in page.iter_cells() {
+                // Entries set whole squares, which can define flat cells
+                assert!(!page.is_defined(x, z) || other.is_defined(x, z));
+                let restored_cell = other.get_cell(x, z).unwrap();
+                let at = (page_x, page_z, x, z);
+                assert_eq!(cell.height, restored_cell.height, "{at:?}");
+                assert_eq!(cell.texture_id, restored_cell.texture_id, "{at:?}");
+                assert_eq!(cell.rotation, restored_cell.rotation, "{at:?}");
+            }
+        }
+        // The uniform half takes few entries
+        let north = dump
+            .entries
+            .iter()
+            .filter(|entry| (entry.page_x, entry.page_z) == (0, 0) && entry.node_z < 64)
+            .count();
+        assert!(north <= 2, "{north} entries");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let map =
+            map_of("elevdump version 2\n0 0 2 4 1 1 4 16389 10 20 30 40\n-1 3 0 0 64 1 1 5 7\n");
+        let json = serde_json::to_string(&map).unwrap();
+        let restored: ElevMap = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.page_count(), 2);
+        for (&(page_x, page_z), page) in map.iter_pages() {
+            let other = restored.get_page(page_x, page_z).unwrap();
+            assert_eq!(page.defined, other.defined);
+            for (x, z, cell) in page.iter_cells() {
+                let restored_cell = other.get_cell(x, z).unwrap();
+                assert_eq!(cell.height, restored_cell.height);
+                assert_eq!(cell.texture_id, restored_cell.texture_id);
+                assert_eq!(cell.rotation, restored_cell.rotation);
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_rejects_duplicate_pages() {
+        let map = map_of("elevdump version 2\n0 0 0 0 64 1 1 5 7\n");
+        let page = serde_json::to_value(&map).unwrap()[0].clone();
+        let json = serde_json::Value::Array(vec![page.clone(), page]).to_string();
+
+        let error = serde_json::from_str::<ElevMap>(&json).unwrap_err();
+        assert!(error.to_string().contains("Duplicate page 0, 0"));
+    }
+}
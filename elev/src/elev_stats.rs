@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+
+use super::{ElevDump, ElevMap};
+
+/// Percentiles reported in [`HeightStats::percentiles`].
+pub const PERCENTILES: [u8; 7] = [1, 5, 25, 50, 75, 95, 99];
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ElevStats {
+    pub entry_count: usize,
+    pub page_count: usize,
+    /// Cells set by some entry, leaving out those of a page that none covers
+    pub cell_count: usize,
+    pub heights: Option<HeightStats>,
+    pub textures: Vec<TextureUsage>,
+    /// Number of entries using each node radius
+    pub node_radii: BTreeMap<u8, usize>,
+    pub pages: Vec<PageSummary>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeightStats {
+    pub min: i32,
+    pub max: i32,
+    pub mean: f64,
+    /// (percentile, height) pairs for each of [`PERCENTILES`]
+    pub percentiles: Vec<(u8, i32)>,
+    pub histogram: HeightHistogram,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeightHistogram {
+    /// Lowest height covered by the first bin
+    pub start: i32,
+    /// Heights covered by each bin, which can exceed `i32::MAX` when the
+    /// heights span the whole range of an `i32`
+    pub bin_width: i64,
+    pub counts: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TextureUsage {
+    pub texture_id: u32,
+    pub count: usize,
+    /// Cell counts indexed by rotation (R0, R1, R2, R3)
+    pub rotations: [usize; 4],
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PageSummary {
+    pub page_x: i32,
+    pub page_z: i32,
+    pub min_height: i32,
+    pub max_height: i32,
+    pub mean_height: f64,
+    /// Number of distinct textures used on the page
+    pub texture_count: usize,
+}
+
+impl HeightHistogram {
+    pub const DEFAULT_BINS: usize = 32;
+
+    fn new(sorted_heights: &[i32], bins: usize) -> Self {
+        let (Some(&min), Some(&max)) = (sorted_heights.first(), sorted_heights.last()) else {
+            return HeightHistogram {
+                start: 0,
+                bin_width: 1,
+                counts: Vec::new(),
+            };
+        };
+
+        let bins = bins.max(1);
+        let range = i64::from(max) - i64::from(min) + 1;
+        let bin_width = ((range + bins as i64 - 1) / bins as i64).max(1);
+        let mut counts = vec![0; bins];
+
+        for &height in sorted_heights {
+            let bin = ((i64::from(height) - i64::from(min)) / bin_width) as usize;
+            counts[bin.min(bins - 1)] += 1;
+        }
+
+        HeightHistogram {
+            start: min,
+            bin_width,
+            counts,
+        }
+    }
+
+    /// Iterates over `(bin_start, count)` pairs
+    pub fn iter_bins(&self) -> impl Iterator<Item = (i64, usize)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| (i64::from(self.start) + i as i64 * self.bin_width, count))
+    }
+}
+
+impl HeightStats {
+    fn new(mut heights: Vec<i32>, bins: usize) -> Option<Self> {
+        if heights.is_empty() {
+            return None;
+        }
+
+        heights.sort_unstable();
+
+        let sum: i64 = heights.iter().map(|&h| i64::from(h)).sum();
+        let percentiles = PERCENTILES
+            .iter()
+            .map(|&p| {
+                let index = (heights.len() - 1) * usize::from(p) / 100;
+                (p, heights[index])
+            })
+            .collect();
+
+        Some(HeightStats {
+            min: heights[0],
+            max: heights[heights.len() - 1],
+            mean: sum as f64 / heights.len() as f64,
+            percentiles,
+            histogram: HeightHistogram::new(&heights, bins),
+        })
+    }
+}
+
+impl ElevStats {
+    pub fn new(dump: &ElevDump, map: &ElevMap) -> Self {
+        Self::with_histogram_bins(dump, map, HeightHistogram::DEFAULT_BINS)
+    }
+
+    pub fn with_histogram_bins(dump: &ElevDump, map: &ElevMap, bins: usize) -> Self {
+        let mut node_radii = BTreeMap::new();
+        for entry in &dump.entries {
+            *node_radii.entry(entry.node_radius).or_insert(0) += 1;
+        }
+
+        let mut heights = Vec::with_capacity(map.page_count() * 128 * 128);
+        let mut textures: BTreeMap<u32, TextureUsage> = BTreeMap::new();
+        let mut pages = Vec::with_capacity(map.page_count());
+
+        for (&(page_x, page_z), page) in map.iter_pages() {
+            let mut min_height = i32::MAX;
+            let mut max_height = i32::MIN;
+            let mut sum = 0i64;
+            let mut count = 0usize;
+            let mut page_textures = Vec::new();
+
+            // Cells no entry covers are left flat rather than being terrain
+            for (_, _, cell) in page.iter_cells().filter(|&(x, z, _)| page.is_defined(x, z)) {
+                count += 1;
+                heights.push(cell.height);
+                min_height = min_height.min(cell.height);
+                max_height = max_height.max(cell.height);
+                sum += i64::from(cell.height);
+
+                let usage = textures
+                    .entry(cell.texture_id)
+                    .or_insert_with(|| TextureUsage {
+                        texture_id: cell.texture_id,
+                        count: 0,
+                        rotations: [0; 4],
+                    });
+                usage.count += 1;
+                usage.rotations[usize::from(cell.rotation.index())] += 1;
+
+                if !page_textures.contains(&cell.texture_id) {
+                    page_textures.push(cell.texture_id);
+                }
+            }
+
+            if count == 0 {
+                (min_height, max_height) = (0, 0);
+            }
+            pages.push(PageSummary {
+                page_x,
+                page_z,
+                min_height,
+                max_height,
+                mean_height: sum as f64 / count.max(1) as f64,
+                texture_count: page_textures.len(),
+            });
+        }
+
+        pages.sort_by_key(|page| (page.page_z, page.page_x));
+
+        ElevStats {
+            entry_count: dump.entries.len(),
+            page_count: map.page_count(),
+            cell_count: heights.len(),
+            heights: HeightStats::new(heights, bins),
+            textures: textures.into_values().collect(),
+            node_radii,
+            pages,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_only_defined_cells() {
+        let dump = ElevDump::from_str("elevdump version 2\n0 0 10 10 1 1 1 32769 500\n").unwrap();
+        let map = ElevMap::from(&dump);
+        let stats = ElevStats::new(&dump, &map);
+
+        assert_eq!(stats.page_count, 1);
+        assert_eq!(stats.cell_count, 4);
+        let heights = stats.heights.unwrap();
+        assert_eq!((heights.min, heights.max), (500, 500));
+        assert_eq!(heights.histogram.counts.iter().sum::<usize>(), 4);
+        assert_eq!(stats.textures[0].count, 4);
+        assert_eq!(stats.pages[0].mean_height, 500.0);
+    }
+
+    #[test]
+    fn histogram_spans_the_whole_i32_range() {
+        let histogram = HeightHistogram::new(&[i32::MIN, 0, i32::MAX], 1);
+        assert_eq!(histogram.bin_width, 1 << 32);
+        assert_eq!(histogram.counts, vec![3]);
+
+        let histogram = HeightHistogram::new(&[i32::MIN, i32::MAX], 4);
+        let starts: Vec<i64> = histogram.iter_bins().map(|(start, _)| start).collect();
+        assert_eq!(starts, vec![-(1 << 31), -(1 << 30), 0, 1 << 30]);
+        assert_eq!(histogram.counts, vec![1, 0, 0, 1]);
+    }
+}
@@ -1,9 +1,9 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::Path;
 use thiserror::Error;
 
-use super::{ElevEntry, ElevEntryError};
+use super::{Compression, ElevEntry, ElevEntryError};
 
 #[derive(Debug, Error)]
 pub enum ElevDumpError {
@@ -13,50 +13,319 @@ pub enum ElevDumpError {
     #[error("Invalid elevdump version: expected 'elevdump version 2', got '{0}'")]
     InvalidVersion(String),
 
-    #[error("ElevEntry error: {0}")]
-    ElevEntryError(#[from] ElevEntryError),
+    #[error("ElevEntry error on line {line}: {source}")]
+    ElevEntryError {
+        line: usize,
+        #[source]
+        source: ElevEntryError,
+    },
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ElevDump {
     pub entries: Vec<ElevEntry>,
 }
 
+const VERSION_LINE: &str = "elevdump version 2";
+
+/// Bytes of whole lines read at a time before they are parsed, in parallel
+/// with the `rayon` feature, which bounds the memory used besides the entries
+const BATCH_LEN: usize = 8 * 1024 * 1024;
+
 impl ElevDump {
+    /// Reads an elevdump from a file, transparently decompressing it if it
+    /// is gzip or zstd compressed.
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ElevDumpError> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        Self::from_reader(reader)
+        Self::from_reader(BufReader::new(File::open(path)?))
     }
 
+    /// Like [`ElevDump::from_file`], but reads from stdin when `path` is `-`.
+    pub fn from_file_or_stdin<P: AsRef<Path>>(path: P) -> Result<Self, ElevDumpError> {
+        if path.as_ref() == Path::new("-") {
+            Self::from_reader(std::io::stdin().lock())
+        } else {
+            Self::from_file(path)
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Result<Self, ElevDumpError> {
-        let reader = BufReader::new(s.as_bytes());
-        Self::from_reader(reader)
+        Self::from_reader(s.as_bytes())
+    }
+
+    /// Parses an elevdump held in memory, which may be compressed.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ElevDumpError> {
+        Self::from_reader(data)
     }
 
-    fn from_reader<R: BufRead>(reader: R) -> Result<Self, ElevDumpError> {
-        let mut lines = reader.lines();
+    /// Parses an elevdump as it is read, transparently decompressing it if
+    /// it is gzip or zstd compressed. Lines are read in batches of a few
+    /// megabytes, and with the `rayon` feature enabled the entries of each
+    /// batch are parsed on all available threads.
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self, ElevDumpError> {
+        let mut reader = Compression::decoder(reader)?;
+        let mut batch = Vec::new();
 
         // Check the version
-        if let Some(first_line) = lines.next() {
-            let first_line = first_line?;
-            if first_line.trim() != "elevdump version 2" {
-                return Err(ElevDumpError::InvalidVersion(first_line));
-            }
-        } else {
+        if reader.read_until(b'\n', &mut batch)? == 0 {
             return Err(ElevDumpError::InvalidVersion("".to_string()));
         }
+        let first_line = String::from_utf8_lossy(&batch);
+        if first_line.trim() != VERSION_LINE {
+            return Err(ElevDumpError::InvalidVersion(
+                first_line.trim_end_matches(['\r', '\n']).to_string(),
+            ));
+        }
 
         let mut entries = Vec::new();
-
-        for line in lines {
-            let line = line?;
-            if !line.trim().is_empty() {
-                let entry = ElevEntry::from_line(line)?;
-                entries.push(entry);
+        let mut line = 2;
+        loop {
+            batch.clear();
+            while batch.len() < BATCH_LEN {
+                if reader.read_until(b'\n', &mut batch)? == 0 {
+                    break;
+                }
+            }
+            if batch.is_empty() {
+                break;
             }
+
+            #[cfg(feature = "rayon")]
+            parse_batch_parallel(&batch, line, &mut entries)?;
+            #[cfg(not(feature = "rayon"))]
+            parse_batch(&batch, line, &mut entries)?;
+
+            line += count_lines(&batch);
         }
 
         Ok(ElevDump { entries })
     }
+
+    /// Writes the elevdump to a file, compressing it if the file name ends in
+    /// `.gz` or `.zst`.
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), ElevDumpError> {
+        let compression = Compression::from_path(&path);
+        let file = BufWriter::new(File::create(path)?);
+        let mut writer = compression.encoder(file)?;
+        self.write_to(&mut writer)?;
+        writer.finish()?.flush()?;
+        Ok(())
+    }
+
+    /// Like [`ElevDump::to_file`], but writes uncompressed text to stdout
+    /// when `path` is `-`.
+    pub fn to_file_or_stdout<P: AsRef<Path>>(&self, path: P) -> Result<(), ElevDumpError> {
+        if path.as_ref() == Path::new("-") {
+            let mut stdout = BufWriter::new(std::io::stdout().lock());
+            self.write_to(&mut stdout)?;
+            stdout.flush()?;
+            Ok(())
+        } else {
+            self.to_file(path)
+        }
+    }
+
+    /// Writes the elevdump as uncompressed text.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writeln!(writer, "{VERSION_LINE}")?;
+        for entry in &self.entries {
+            writeln!(writer, "{entry}")?;
+        }
+        Ok(())
+    }
+}
+
+fn count_lines(batch: &[u8]) -> usize {
+    batch.iter().filter(|&&b| b == b'\n').count()
+}
+
+/// Parses the entries of whole lines, the first of which is line `line` of
+/// the elevdump, stopping at the first that is malformed
+fn parse_lines(
+    batch: &[u8],
+    line: usize,
+    entries: &mut Vec<ElevEntry>,
+) -> Result<(), ElevDumpError> {
+    for (index, text) in batch.split(|&b| b == b'\n').enumerate() {
+        if text.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        let entry =
+            ElevEntry::from_bytes(text).map_err(|source| ElevDumpError::ElevEntryError {
+                line: line + index,
+                source,
+            })?;
+        entries.push(entry);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "rayon"))]
+fn parse_batch(
+    batch: &[u8],
+    line: usize,
+    entries: &mut Vec<ElevEntry>,
+) -> Result<(), ElevDumpError> {
+    parse_lines(batch, line, entries)
+}
+
+/// Splits a batch into line-aligned chunks, parses them concurrently and
+/// appends their entries in their original order. When several chunks are
+/// malformed, the error of the earliest is returned.
+#[cfg(feature = "rayon")]
+fn parse_batch_parallel(
+    batch: &[u8],
+    line: usize,
+    entries: &mut Vec<ElevEntry>,
+) -> Result<(), ElevDumpError> {
+    use rayon::prelude::*;
+
+    const MIN_CHUNK_LEN: usize = 64 * 1024;
+
+    let chunk_len = (batch.len() / (rayon::current_num_threads() * 4)).max(MIN_CHUNK_LEN);
+
+    let mut chunks = Vec::new();
+    let mut rest = batch;
+    while !rest.is_empty() {
+        let split = match rest
+            .get(chunk_len..)
+            .and_then(|tail| tail.iter().position(|&b| b == b'\n'))
+        {
+            Some(newline) => chunk_len + newline + 1,
+            None => rest.len(),
+        };
+        let (chunk, tail) = rest.split_at(split);
+        chunks.push(chunk);
+        rest = tail;
+    }
+
+    // Each chunk numbers its lines from zero, as the lines before it are
+    // only counted once its error is known to be the first
+    let parsed: Vec<Result<Vec<ElevEntry>, ElevDumpError>> = chunks
+        .par_iter()
+        .map(|chunk| {
+            let mut entries = Vec::new();
+            parse_lines(chunk, 0, &mut entries).map(|()| entries)
+        })
+        .collect();
+
+    let mut line = line;
+    for (chunk, result) in chunks.iter().zip(parsed) {
+        match result {
+            Ok(chunk_entries) => entries.extend(chunk_entries),
+            Err(ElevDumpError::ElevEntryError {
+                line: index,
+                source,
+            }) => {
+                return Err(ElevDumpError::ElevEntryError {
+                    line: line + index,
+                    source,
+                })
+            }
+            Err(error) => return Err(error),
+        }
+        line += count_lines(chunk);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An elevdump of `count` entries spread over many pages
+    fn sample_dump(count: i32) -> String {
+        let mut text = format!("{VERSION_LINE}\n");
+        for i in 0..count {
+            text.push_str(&format!(
+                "{} {} {} {} 2 1 16 {} {}\n",
+                i % 50 - 25,
+                i / 50 - 25,
+                i % 120,
+                (i * 7) % 120,
+                0x8000 + i % 900,
+                (0..16)
+                    .map(|h| (i * 16 + h - 4000).to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ));
+        }
+        text
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        // Large enough to be split into several chunks when parsed in parallel
+        let text = sample_dump(5000);
+        assert!(text.len() > 4 * 64 * 1024);
+
+        let dump = ElevDump::from_str(&text).unwrap();
+        assert_eq!(dump.entries.len(), 5000);
+        assert_eq!(dump.entries[4321].page_x, 4321 % 50 - 25);
+
+        let mut written = Vec::new();
+        dump.write_to(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), text);
+    }
+
+    #[test]
+    fn skips_blank_lines_and_carriage_returns() {
+        let dump =
+            ElevDump::from_str("elevdump version 2\r\n\r\n0 0 0 0 1 1 1 5 7\r\n  \n").unwrap();
+        assert_eq!(dump.entries.len(), 1);
+        assert_eq!(dump.entries[0].heights, vec![7]);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        assert!(matches!(
+            ElevDump::from_str(""),
+            Err(ElevDumpError::InvalidVersion(version)) if version.is_empty()
+        ));
+        assert!(matches!(
+            ElevDump::from_str("elevdump version 3\n"),
+            Err(ElevDumpError::InvalidVersion(version)) if version == "elevdump version 3"
+        ));
+    }
+
+    #[test]
+    fn reports_the_earliest_malformed_entry() {
+        let mut lines: Vec<String> = sample_dump(5000).lines().map(str::to_string).collect();
+        // Malformed entries in what become different chunks, the later one
+        // the quicker to parse
+        lines[3000] = "0 0 0 0 1 1 1 5 x".to_string();
+        lines[4990] = "0".to_string();
+        let text = lines.join("\n");
+
+        match ElevDump::from_str(&text) {
+            Err(ElevDumpError::ElevEntryError { line, source }) => {
+                assert_eq!(line, 3001);
+                assert!(matches!(source, ElevEntryError::ParseIntError("height", _)));
+            }
+            other => panic!("expected an entry error, got {other:?}"),
+        }
+    }
+
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    #[test]
+    fn writes_and_reads_compressed_files() {
+        let dump = ElevDump::from_str(&sample_dump(100)).unwrap();
+        let dir = std::env::temp_dir().join(format!("elevdump-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for (name, magic) in [
+            ("dump.txt.gz", &[0x1f, 0x8b][..]),
+            ("dump.zst", &[0x28, 0xb5]),
+        ] {
+            let path = dir.join(name);
+            dump.to_file(&path).unwrap();
+            assert!(std::fs::read(&path).unwrap().starts_with(magic));
+
+            let read = ElevDump::from_file(&path).unwrap();
+            assert_eq!(read.entries.len(), 100);
+            assert_eq!(read.entries[99].to_string(), dump.entries[99].to_string());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
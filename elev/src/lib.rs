@@ -1,8 +1,22 @@
+mod compression;
+pub use compression::{CompressedWriter, Compression};
+
 mod elev_entry;
 pub use elev_entry::{ElevEntry, ElevEntryError};
 
 mod elev_map;
 pub use elev_map::{ElevCell, ElevMap, ElevPage, Rotation};
 
+mod elev_stats;
+pub use elev_stats::{
+    ElevStats, HeightHistogram, HeightStats, PageSummary, TextureUsage, PERCENTILES,
+};
+
+mod orientation;
+pub use orientation::{Direction, Orientation};
+
+mod texture_resolver;
+pub use texture_resolver::{ResolvedTextures, TextureLocation, TextureResolver};
+
 mod elevdump;
 pub use elevdump::{ElevDump, ElevDumpError};
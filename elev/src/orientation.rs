@@ -0,0 +1,173 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A compass direction in the world, in which x grows to the west and z to
+/// the north
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Direction {
+    #[default]
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Direction {
+    /// Change in world cell coordinates `(x, z)` of one step this way
+    pub fn world_step(self) -> (i32, i32) {
+        match self {
+            Direction::North => (0, 1),
+            Direction::East => (-1, 0),
+            Direction::South => (0, -1),
+            Direction::West => (1, 0),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::North => "north",
+            Direction::East => "east",
+            Direction::South => "south",
+            Direction::West => "west",
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "north" | "n" => Ok(Direction::North),
+            "east" | "e" => Ok(Direction::East),
+            "south" | "s" => Ok(Direction::South),
+            "west" | "w" => Ok(Direction::West),
+            _ => Err(format!("Expected north, east, south or west, got {s:?}")),
+        }
+    }
+}
+
+/// How the cells of a map are laid out in an image: which direction is at
+/// the top, and whether the image is mirrored left to right. The default is
+/// north up and east to the right, as on a conventional map.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Orientation {
+    pub up: Direction,
+    pub mirrored: bool,
+}
+
+impl Orientation {
+    pub fn new(up: Direction, mirrored: bool) -> Self {
+        Orientation { up, mirrored }
+    }
+
+    /// Change in world cell coordinates `(x, z)` of one pixel to the right
+    pub fn right(self) -> (i32, i32) {
+        let (up_x, up_z) = self.up.world_step();
+        let sign = if self.mirrored { -1 } else { 1 };
+        (-up_z * sign, up_x * sign)
+    }
+
+    /// Change in world cell coordinates `(x, z)` of one pixel down
+    pub fn down(self) -> (i32, i32) {
+        let (up_x, up_z) = self.up.world_step();
+        (-up_x, -up_z)
+    }
+
+    /// Width and height in pixels of an image of `cells_x` by `cells_z`
+    /// cells, which swap places when east or west is up
+    pub fn image_size(self, cells_x: u32, cells_z: u32) -> (u32, u32) {
+        if self.right().0 != 0 {
+            (cells_x, cells_z)
+        } else {
+            (cells_z, cells_x)
+        }
+    }
+
+    /// The cell at pixel (0, 0) of an image covering world cells `min` to
+    /// `max` inclusive, each given as `(x, z)`
+    pub fn origin(self, min: (i32, i32), max: (i32, i32)) -> (i32, i32) {
+        let (right, down) = (self.right(), self.down());
+        let pick = |step: i32, min: i32, max: i32| if step < 0 { max } else { min };
+        (
+            pick(right.0 + down.0, min.0, max.0),
+            pick(right.1 + down.1, min.1, max.1),
+        )
+    }
+
+    /// World cell coordinates of the cell at a pixel of an image whose pixel
+    /// (0, 0) is the cell `origin`
+    pub fn world_cell(self, origin: (i32, i32), pixel_x: u32, pixel_y: u32) -> (i32, i32) {
+        let (right, down) = (self.right(), self.down());
+        // Images can be wider than i32::MAX pixels, so this is done in i64
+        // and wraps only for cells beyond the world's coordinates
+        let along = |origin: i32, right: i32, down: i32| {
+            (i64::from(origin)
+                + i64::from(pixel_x) * i64::from(right)
+                + i64::from(pixel_y) * i64::from(down)) as i32
+        };
+        (
+            along(origin.0, right.0, down.0),
+            along(origin.1, right.1, down.1),
+        )
+    }
+}
+
+/// Formats the orientation as, for example, "north-up" or "east-up mirrored"
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-up", self.up)?;
+        if self.mirrored {
+            f.write_str(" mirrored")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_across_images_in_every_orientation() {
+        // Cells x 0 to 2 and z 0 to 1, with x growing to the west
+        for (up, mirrored, size, corners) in [
+            (Direction::North, false, (3, 2), [(2, 1), (0, 1), (2, 0)]),
+            (Direction::North, true, (3, 2), [(0, 1), (2, 1), (0, 0)]),
+            (Direction::South, false, (3, 2), [(0, 0), (2, 0), (0, 1)]),
+            (Direction::East, false, (2, 3), [(0, 1), (0, 0), (2, 1)]),
+            (Direction::West, false, (2, 3), [(2, 0), (2, 1), (0, 0)]),
+        ] {
+            let orientation = Orientation::new(up, mirrored);
+            assert_eq!(orientation.image_size(3, 2), size);
+            let origin = orientation.origin((0, 0), (2, 1));
+            let (width, height) = size;
+            let at = |x, y| orientation.world_cell(origin, x, y);
+            assert_eq!(
+                [at(0, 0), at(width - 1, 0), at(0, height - 1)],
+                corners,
+                "{orientation}"
+            );
+        }
+    }
+
+    #[test]
+    fn reaches_cells_more_than_i32_max_pixels_away() {
+        let orientation = Orientation::default();
+        let origin = orientation.origin((i32::MIN, 0), (i32::MAX, 0));
+        assert_eq!(origin, (i32::MAX, 0));
+        assert_eq!(orientation.world_cell(origin, u32::MAX, 0), (i32::MIN, 0));
+    }
+}
@@ -0,0 +1,437 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Finds terrain texture files by texture id.
+///
+/// File names are produced from a pattern in which `{id}` is replaced by the
+/// texture id, e.g. the default `terrain{id}`. If the pattern has no
+/// extension, each of [`TextureResolver::EXTENSIONS`] is tried in turn.
+///
+/// With the `zip` feature, the source may also be a zip archive of textures,
+/// and a texture may be a zipped `terrain{id}.zip` as served by an Active
+/// Worlds object path, either in a directory or inside such an archive.
+#[derive(Debug)]
+pub struct TextureResolver {
+    source: PathBuf,
+    pattern: String,
+    #[cfg(feature = "zip")]
+    archives: zip_source::ArchiveCache,
+}
+
+/// Where a texture's file was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextureLocation {
+    File(PathBuf),
+    /// A file inside a zip archive. Each element of `entries` after the first
+    /// names a file inside the zip archive named by the previous one.
+    Zip {
+        archive: PathBuf,
+        entries: Vec<String>,
+    },
+}
+
+/// The outcome of resolving every texture referenced by a map.
+#[derive(Debug, Default)]
+pub struct ResolvedTextures {
+    pub found: BTreeMap<u32, TextureLocation>,
+    pub missing: BTreeSet<u32>,
+}
+
+impl TextureLocation {
+    /// The file on disk holding the texture
+    pub fn file_path(&self) -> &Path {
+        match self {
+            TextureLocation::File(path) => path,
+            TextureLocation::Zip { archive, .. } => archive,
+        }
+    }
+
+    /// The extension of the texture image itself
+    pub fn extension(&self) -> Option<&str> {
+        let name = match self {
+            TextureLocation::File(path) => path.to_str()?,
+            TextureLocation::Zip { entries, .. } => entries.last()?,
+        };
+        Path::new(name).extension()?.to_str()
+    }
+}
+
+impl fmt::Display for TextureLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureLocation::File(path) => write!(f, "{}", path.display()),
+            TextureLocation::Zip { archive, entries } => {
+                write!(f, "{}", archive.display())?;
+                for entry in entries {
+                    write!(f, "!{entry}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl TextureResolver {
+    pub const DEFAULT_PATTERN: &'static str = "terrain{id}";
+    pub const EXTENSIONS: [&'static str; 5] = ["jpg", "jpeg", "png", "bmp", "dds"];
+
+    /// `source` is a directory of textures or, with the `zip` feature, a
+    /// zip archive of them.
+    pub fn new(source: impl Into<PathBuf>) -> Self {
+        TextureResolver {
+            source: source.into(),
+            pattern: Self::DEFAULT_PATTERN.to_string(),
+            #[cfg(feature = "zip")]
+            archives: Default::default(),
+        }
+    }
+
+    pub fn with_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.pattern = pattern.into();
+        self
+    }
+
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// The file name for `texture_id`, without an extension unless the
+    /// pattern provides one.
+    pub fn file_stem(&self, texture_id: u32) -> String {
+        self.pattern.replace("{id}", &texture_id.to_string())
+    }
+
+    /// Candidate file names for `texture_id`, in order of preference
+    fn file_names(&self, texture_id: u32) -> Vec<String> {
+        let name = self.file_stem(texture_id);
+        if Path::new(&name).extension().is_some() {
+            return vec![name];
+        }
+
+        Self::EXTENSIONS
+            .iter()
+            .map(|ext| format!("{name}.{ext}"))
+            .collect()
+    }
+
+    /// The path a texture is expected at when the source cannot be
+    /// searched, using the first of [`TextureResolver::EXTENSIONS`] if the
+    /// pattern has no extension.
+    pub fn default_path(&self, texture_id: u32) -> PathBuf {
+        let mut names = self.file_names(texture_id);
+        self.source.join(names.swap_remove(0))
+    }
+
+    pub fn resolve(&self, texture_id: u32) -> Option<TextureLocation> {
+        let names = self.file_names(texture_id);
+
+        #[cfg(feature = "zip")]
+        if self.source.is_file() {
+            return self
+                .archives
+                .find(&self.source, &names, &self.zip_name(texture_id));
+        }
+
+        if let Some(path) = names
+            .iter()
+            .map(|name| self.source.join(name))
+            .find(|path| path.is_file())
+        {
+            return Some(TextureLocation::File(path));
+        }
+
+        #[cfg(feature = "zip")]
+        {
+            let archive = self.source.join(self.zip_name(texture_id));
+            if archive.is_file() {
+                return self.archives.find(&archive, &names, "");
+            }
+        }
+
+        None
+    }
+
+    pub fn resolve_all(&self, texture_ids: impl IntoIterator<Item = u32>) -> ResolvedTextures {
+        let mut resolved = ResolvedTextures::default();
+        for texture_id in texture_ids {
+            match self.resolve(texture_id) {
+                Some(location) => {
+                    resolved.found.insert(texture_id, location);
+                }
+                None => {
+                    resolved.missing.insert(texture_id);
+                }
+            }
+        }
+        resolved
+    }
+
+    /// Every texture id that has a texture in the source, found by matching
+    /// the names of its files, ignoring case inside zip archives, against the
+    /// pattern. Textures in subdirectories of a directory are not searched.
+    pub fn available_ids(&self) -> io::Result<BTreeSet<u32>> {
+        #[cfg(feature = "zip")]
+        if self.source.is_file() {
+            let names = self.archives.file_names(&self.source)?;
+            return Ok(self.ids_named(names.iter().map(String::as_str), true));
+        }
+
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(&self.source)? {
+            let entry = entry?;
+            if entry.path().is_file() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        Ok(self.ids_named(names.iter().map(String::as_str), false))
+    }
+
+    /// The ids of the textures that [`TextureResolver::resolve`] would find
+    /// under any of `names`
+    fn ids_named<'a>(
+        &self,
+        names: impl Iterator<Item = &'a str>,
+        ignore_case: bool,
+    ) -> BTreeSet<u32> {
+        let Some((prefix, _)) = self.pattern.split_once("{id}") else {
+            return BTreeSet::new();
+        };
+        let same = |a: &str, b: &str| {
+            if ignore_case {
+                a.eq_ignore_ascii_case(b)
+            } else {
+                a == b
+            }
+        };
+
+        names
+            .filter_map(|name| {
+                let rest = name.get(prefix.len()..)?;
+                if !same(&name[..prefix.len()], prefix) {
+                    return None;
+                }
+                let digits =
+                    rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                let texture_id: u32 = rest[..digits].parse().ok()?;
+
+                #[cfg(feature = "zip")]
+                let zipped = same(&self.zip_name(texture_id), name);
+                #[cfg(not(feature = "zip"))]
+                let zipped = false;
+
+                let named = zipped
+                    || self
+                        .file_names(texture_id)
+                        .iter()
+                        .any(|file_name| same(file_name, name));
+                named.then_some(texture_id)
+            })
+            .collect()
+    }
+
+    /// Reads the contents of a texture file, extracting it from its zip
+    /// archives if necessary. Extracted files are kept in memory, so reading
+    /// the same location again is cheap.
+    pub fn read(&self, location: &TextureLocation) -> io::Result<Vec<u8>> {
+        match location {
+            TextureLocation::File(path) => std::fs::read(path),
+            #[cfg(feature = "zip")]
+            TextureLocation::Zip { archive, entries } => self.archives.read(archive, entries),
+            #[cfg(not(feature = "zip"))]
+            TextureLocation::Zip { .. } => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "reading zipped textures requires the 'zip' feature of the elev crate",
+            )),
+        }
+    }
+
+    #[cfg(feature = "zip")]
+    fn zip_name(&self, texture_id: u32) -> String {
+        let name = self.file_stem(texture_id);
+        match Path::new(&name).file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) if Path::new(&name).extension().is_some() => format!("{stem}.zip"),
+            _ => format!("{name}.zip"),
+        }
+    }
+}
+
+#[cfg(feature = "zip")]
+mod zip_source {
+    use std::collections::HashMap;
+    use std::io::{self, Cursor, Read};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+
+    use super::TextureLocation;
+
+    type Archive = zip::ZipArchive<Cursor<Arc<[u8]>>>;
+
+    /// An archive path followed by the chain of entries leading to a file
+    type EntryKey = (PathBuf, Vec<String>);
+
+    /// Opened archives and extracted files
+    #[derive(Debug, Default)]
+    pub(super) struct ArchiveCache {
+        archives: Mutex<HashMap<EntryKey, Archive>>,
+        files: Mutex<HashMap<EntryKey, Arc<[u8]>>>,
+    }
+
+    fn zip_error(error: zip::result::ZipError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+
+    /// Finds the entry whose file name, ignoring directories and case, is
+    /// `name`.
+    fn find_entry(archive: &Archive, name: &str) -> Option<String> {
+        archive
+            .file_names()
+            .find(|entry| {
+                let file_name = entry.rsplit(['/', '\\']).next().unwrap_or(entry);
+                file_name.eq_ignore_ascii_case(name)
+            })
+            .map(str::to_string)
+    }
+
+    impl ArchiveCache {
+        /// Looks in the zip archive `path` for any of `names`, then for a
+        /// nested zip archive named `zip_name` containing one of them.
+        pub(super) fn find(
+            &self,
+            path: &Path,
+            names: &[String],
+            zip_name: &str,
+        ) -> Option<TextureLocation> {
+            let find_in = |entries: Vec<String>| -> Option<Vec<String>> {
+                let mut archives = self.archives.lock().unwrap();
+                let archive = self.open(&mut archives, path, &entries).ok()?;
+                let found = names.iter().find_map(|name| find_entry(archive, name));
+                found.map(|name| [entries, vec![name]].concat())
+            };
+
+            if let Some(entries) = find_in(Vec::new()) {
+                return Some(TextureLocation::Zip {
+                    archive: path.to_path_buf(),
+                    entries,
+                });
+            }
+
+            if zip_name.is_empty() {
+                return None;
+            }
+
+            let nested = {
+                let mut archives = self.archives.lock().unwrap();
+                let archive = self.open(&mut archives, path, &[]).ok()?;
+                find_entry(archive, zip_name)?
+            };
+
+            find_in(vec![nested]).map(|entries| TextureLocation::Zip {
+                archive: path.to_path_buf(),
+                entries,
+            })
+        }
+
+        /// The file names, without their directories, of the entries of the
+        /// zip archive `path`
+        pub(super) fn file_names(&self, path: &Path) -> io::Result<Vec<String>> {
+            let mut archives = self.archives.lock().unwrap();
+            let archive = self.open(&mut archives, path, &[])?;
+            Ok(archive
+                .file_names()
+                .filter(|entry| !entry.ends_with(['/', '\\']))
+                .map(|entry| {
+                    entry
+                        .rsplit(['/', '\\'])
+                        .next()
+                        .unwrap_or(entry)
+                        .to_string()
+                })
+                .collect())
+        }
+
+        pub(super) fn read(&self, path: &Path, entries: &[String]) -> io::Result<Vec<u8>> {
+            let key = (path.to_path_buf(), entries.to_vec());
+            if let Some(data) = self.files.lock().unwrap().get(&key) {
+                return Ok(data.to_vec());
+            }
+
+            let Some((name, parents)) = entries.split_last() else {
+                return std::fs::read(path);
+            };
+
+            let data: Arc<[u8]> = {
+                let mut archives = self.archives.lock().unwrap();
+                let archive = self.open(&mut archives, path, parents)?;
+                let mut file = archive.by_name(name).map_err(zip_error)?;
+                let mut data = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut data)?;
+                data.into()
+            };
+
+            let contents = data.to_vec();
+            self.files.lock().unwrap().insert(key, data);
+            Ok(contents)
+        }
+
+        fn open<'a>(
+            &self,
+            archives: &'a mut HashMap<EntryKey, Archive>,
+            path: &Path,
+            entries: &[String],
+        ) -> io::Result<&'a mut Archive> {
+            let key = (path.to_path_buf(), entries.to_vec());
+            if !archives.contains_key(&key) {
+                let data = match entries.split_last() {
+                    None => std::fs::read(path)?.into(),
+                    Some((name, parents)) => {
+                        let parent = self.open(archives, path, parents)?;
+                        let mut file = parent.by_name(name).map_err(zip_error)?;
+                        let mut data = Vec::with_capacity(file.size() as usize);
+                        file.read_to_end(&mut data)?;
+                        Arc::from(data)
+                    }
+                };
+                let archive = zip::ZipArchive::new(Cursor::new(data)).map_err(zip_error)?;
+                archives.insert(key.clone(), archive);
+            }
+            Ok(archives.get_mut(&key).unwrap())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_the_textures_the_pattern_names() {
+        let dir = std::env::temp_dir().join(format!("elev-textures-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("terrain9.jpg")).unwrap();
+        for name in [
+            "terrain0.jpg",
+            "terrain12.png",
+            "terrain12.bmp",
+            "terrain3.zip",
+            "terrain4.txt",
+            "terrain.jpg",
+            "Terrain5.jpg",
+            "other6.jpg",
+        ] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let resolver = TextureResolver::new(&dir);
+        let mut expected = BTreeSet::from([0, 12]);
+        if cfg!(feature = "zip") {
+            expected.insert(3);
+        }
+        assert_eq!(resolver.available_ids().unwrap(), expected);
+
+        let resolver = TextureResolver::new(&dir).with_pattern("other{id}.jpg");
+        assert_eq!(resolver.available_ids().unwrap(), BTreeSet::from([6]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
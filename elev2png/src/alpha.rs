@@ -0,0 +1,32 @@
+use image::RgbaImage;
+
+/// Scales colours by their opacity, so that filtering an image doesn't
+/// bleed the colour of transparent pixels into their neighbours
+pub fn premultiply(img: &mut RgbaImage) {
+    for pixel in img.pixels_mut() {
+        let alpha = u32::from(pixel[3]);
+        for channel in &mut pixel.0[..3] {
+            *channel = ((u32::from(*channel) * alpha + 127) / 255) as u8;
+        }
+    }
+}
+
+/// Undoes [`premultiply`]
+pub fn unpremultiply(img: &mut RgbaImage) {
+    for pixel in img.pixels_mut() {
+        let alpha = u32::from(pixel[3]);
+        if alpha == 0 {
+            continue;
+        }
+        for channel in &mut pixel.0[..3] {
+            *channel = ((u32::from(*channel) * 255 + alpha / 2) / alpha).min(255) as u8;
+        }
+    }
+}
+
+/// Makes every pixel opaque, leaving those with nothing drawn black
+pub fn make_opaque(img: &mut RgbaImage) {
+    for pixel in img.pixels_mut() {
+        pixel[3] = 255;
+    }
+}
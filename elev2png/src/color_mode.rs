@@ -0,0 +1,187 @@
+use clap::ValueEnum;
+use elev::{ElevCell, Rotation};
+use image::Rgb;
+
+use crate::gradient::Gradient;
+use crate::overwrites::OverwriteCounts;
+use crate::palette::Palette;
+use crate::texture_catalog::{ColorSummary, TextureCatalog};
+use crate::texture_patches::TexturePatches;
+
+/// How cells are coloured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorModeKind {
+    /// Texture colours, shaded by depth
+    #[default]
+    Texture,
+    /// Greyscale heightmap
+    Height,
+    /// Hypsometric tint from a gradient
+    Hypsometric,
+    /// Steepness of the terrain
+    Slope,
+    /// A distinct false colour for every texture id
+    TextureId,
+    /// A distinct colour for each texture rotation
+    Rotation,
+    /// How many entries of the dump set each cell, from blue for one through
+    /// green and yellow to red for eight or more
+    Overwrites,
+}
+
+pub enum ColorMode {
+    Texture(TextureColoring),
+    Height {
+        min: i32,
+        max: i32,
+    },
+    Hypsometric(Gradient),
+    Slope(Gradient),
+    TextureId,
+    Rotation,
+    Overwrites {
+        counts: OverwriteCounts,
+        gradient: Gradient,
+    },
+}
+
+/// Colours of the texture each cell uses, from a palette, the texture itself
+/// (one patch per cell) or its summary colour, in that order of preference
+pub struct TextureColoring {
+    pub palette: Palette,
+    pub catalog: TextureCatalog,
+    pub patches: Option<TexturePatches>,
+    pub summary: ColorSummary,
+    pub depth: DepthShading,
+}
+
+/// Darkens low terrain and draws everything at or below the water level as
+/// water
+pub struct DepthShading {
+    pub water_level: i32,
+    pub max_height: f32,
+    /// Heights around this are given the most contrast
+    pub contrast_center: f32,
+    pub contrast_width: f32,
+}
+
+impl DepthShading {
+    pub fn is_underwater(&self, height: i32) -> bool {
+        height <= self.water_level
+    }
+
+    pub fn apply(&self, color: Rgb<u8>, height: i32) -> Rgb<u8> {
+        let water_level = self.water_level;
+
+        if height <= water_level {
+            // Underwater: use a brighter blue gradient
+            let depth_factor = (height as f32 / water_level as f32).sqrt();
+            let deep_water = Rgb([30, 30, 180]);
+            let shallow_water = Rgb([150, 150, 255]);
+
+            return Rgb([
+                ((shallow_water[0] as f32 * depth_factor
+                    + deep_water[0] as f32 * (1.0 - depth_factor)) as u8)
+                    .max(30),
+                ((shallow_water[1] as f32 * depth_factor
+                    + deep_water[1] as f32 * (1.0 - depth_factor)) as u8)
+                    .max(30),
+                ((shallow_water[2] as f32 * depth_factor
+                    + deep_water[2] as f32 * (1.0 - depth_factor)) as u8)
+                    .max(180),
+            ]);
+        }
+
+        // Custom depth factor calculation to emphasize heights around the contrast center
+        let base_factor =
+            (height as f32 - water_level as f32) / (self.max_height - water_level as f32);
+        let contrast_factor = 1.0
+            / (1.0 + (-4.0 * (height as f32 - self.contrast_center) / self.contrast_width).exp());
+        let depth_factor = (base_factor * 0.5 + contrast_factor * 0.5).min(1.0);
+
+        // Blend between 80% brightness and full color based on adjusted depth factor
+        let r = (color[0] as f32 * 0.6 + color[0] as f32 * 0.4 * depth_factor) as u8;
+        let g = (color[1] as f32 * 0.6 + color[1] as f32 * 0.4 * depth_factor) as u8;
+        let b = (color[2] as f32 * 0.6 + color[2] as f32 * 0.4 * depth_factor) as u8;
+
+        Rgb([r, g, b])
+    }
+}
+
+impl TextureColoring {
+    pub fn base_color(&self, cell: &ElevCell) -> Rgb<u8> {
+        let tid = cell.texture_id & 1023;
+        self.palette
+            .color(tid, cell.rotation)
+            .or_else(|| self.catalog.color(tid, self.summary))
+            .unwrap_or(Rgb([0, 0, 0]))
+    }
+
+    /// The texel at (`patch_x`, `patch_z`) of the cell's patch, unless the
+    /// palette overrides the texture or no patches were loaded
+    pub fn texel(&self, cell: &ElevCell, patch_x: u32, patch_z: u32) -> Option<Rgb<u8>> {
+        let tid = cell.texture_id & 1023;
+        if self.palette.color(tid, cell.rotation).is_some() {
+            return None;
+        }
+        self.patches
+            .as_ref()?
+            .pixel(tid, cell.rotation, patch_x, patch_z)
+    }
+}
+
+impl ColorMode {
+    /// The colour of a whole cell at world cell coordinates `(x, z)`, for
+    /// modes that do not vary within a cell. `slope` is the steepness of the
+    /// cell in degrees.
+    pub fn cell_color(
+        &self,
+        cell: &ElevCell,
+        (world_x, world_z): (i32, i32),
+        slope: impl FnOnce() -> f64,
+    ) -> Rgb<u8> {
+        match self {
+            ColorMode::Texture(texture) => {
+                texture.depth.apply(texture.base_color(cell), cell.height)
+            }
+            ColorMode::Height { min, max } => {
+                let range = (*max as f64 - *min as f64).max(1.0);
+                let t = ((cell.height as f64 - *min as f64) / range).clamp(0.0, 1.0);
+                let value = (t * 255.0).round() as u8;
+                Rgb([value, value, value])
+            }
+            ColorMode::Hypsometric(gradient) => gradient.color_at(cell.height as f64),
+            ColorMode::Slope(gradient) => gradient.color_at(slope()),
+            ColorMode::TextureId => false_color(cell.texture_id),
+            ColorMode::Rotation => match cell.rotation {
+                Rotation::R0 => Rgb([230, 60, 50]),
+                Rotation::R1 => Rgb([60, 190, 70]),
+                Rotation::R2 => Rgb([50, 100, 230]),
+                Rotation::R3 => Rgb([240, 210, 50]),
+            },
+            ColorMode::Overwrites { counts, gradient } => {
+                gradient.color_at(counts.get(world_x, world_z) as f64)
+            }
+        }
+    }
+}
+
+/// A stable, well separated colour for an id, stepping the hue by the golden
+/// ratio
+fn false_color(id: u32) -> Rgb<u8> {
+    let hue = (id as f64 * 0.618_033_988_75).fract() * 6.0;
+    let (saturation, value) = (0.65, 0.95);
+
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    Rgb([r, g, b].map(|c| ((c + m) * 255.0).round() as u8))
+}
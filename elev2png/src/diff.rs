@@ -0,0 +1,230 @@
+use elev::{ElevCell, ElevMap};
+use image::{Rgb, RgbaImage};
+use rayon::prelude::*;
+
+use crate::extent::Extent;
+use crate::gradient::Gradient;
+
+/// Drawn over cells whose texture or rotation changed but not their height
+const RETEXTURED_COLOR: Rgb<u8> = Rgb([230, 0, 230]);
+/// Drawn over cells that only one of the dumps has
+const ONE_SIDED_COLOR: Rgb<u8> = Rgb([255, 220, 0]);
+
+/// How a cell differs between two dumps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Unchanged,
+    /// New height less old height
+    Height(i64),
+    /// Same height with another texture or rotation
+    Retextured,
+    Added,
+    Removed,
+}
+
+impl Change {
+    /// `None` for cells that neither dump sets
+    fn of(old: Option<&ElevCell>, new: Option<&ElevCell>) -> Option<Self> {
+        Some(match (old, new) {
+            (None, None) => return None,
+            (None, Some(_)) => Change::Added,
+            (Some(_), None) => Change::Removed,
+            (Some(old), Some(new)) if old.height != new.height => {
+                Change::Height(i64::from(new.height) - i64::from(old.height))
+            }
+            (Some(old), Some(new))
+                if old.texture_id != new.texture_id || old.rotation != new.rotation =>
+            {
+                Change::Retextured
+            }
+            _ => Change::Unchanged,
+        })
+    }
+}
+
+/// Counts of the cells of an extent that changed between two dumps
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DiffSummary {
+    pub unchanged: u64,
+    pub raised: u64,
+    pub lowered: u64,
+    pub retextured: u64,
+    pub added: u64,
+    pub removed: u64,
+    /// Largest rise and fall in height, in centimetres
+    pub max_raise: i64,
+    pub max_lower: i64,
+}
+
+impl DiffSummary {
+    fn add(mut self, change: Change) -> Self {
+        match change {
+            Change::Unchanged => self.unchanged += 1,
+            Change::Height(delta) if delta > 0 => {
+                self.raised += 1;
+                self.max_raise = self.max_raise.max(delta);
+            }
+            Change::Height(delta) => {
+                self.lowered += 1;
+                self.max_lower = self.max_lower.max(-delta);
+            }
+            Change::Retextured => self.retextured += 1,
+            Change::Added => self.added += 1,
+            Change::Removed => self.removed += 1,
+        }
+        self
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            unchanged: self.unchanged + other.unchanged,
+            raised: self.raised + other.raised,
+            lowered: self.lowered + other.lowered,
+            retextured: self.retextured + other.retextured,
+            added: self.added + other.added,
+            removed: self.removed + other.removed,
+            max_raise: self.max_raise.max(other.max_raise),
+            max_lower: self.max_lower.max(other.max_lower),
+        }
+    }
+}
+
+/// Compares an older dump with a newer one over the image drawn from the
+/// newer one
+pub struct Diff<'a> {
+    pub old: &'a ElevMap,
+    pub new: &'a ElevMap,
+    pub extent: Extent,
+}
+
+impl Diff<'_> {
+    fn change(&self, pixel_x: u32, pixel_y: u32) -> Option<Change> {
+        let (world_x, world_z) = self.extent.world_cell(pixel_x, pixel_y);
+        Change::of(
+            defined_cell(self.old, world_x, world_z),
+            defined_cell(self.new, world_x, world_z),
+        )
+    }
+
+    pub fn summary(&self) -> DiffSummary {
+        (0..self.extent.height)
+            .into_par_iter()
+            .map(|pixel_y| {
+                (0..self.extent.width)
+                    .filter_map(|pixel_x| self.change(pixel_x, pixel_y))
+                    .fold(DiffSummary::default(), DiffSummary::add)
+            })
+            .reduce(DiffSummary::default, DiffSummary::merge)
+    }
+
+    /// Recolours an image of the newer dump drawn `cell_size` pixels to a
+    /// cell: unchanged cells in grey, height changes from blue for the
+    /// lowest to red for the highest, up to `range` centimetres, and cells
+    /// retextured, added or removed in their own highlights
+    pub fn draw(&self, img: &mut RgbaImage, cell_size: u32, range: u32) {
+        let range = f64::from(range.max(2));
+        let ramp = Gradient::new(vec![
+            (-range, [20.0, 50.0, 170.0]),
+            (-1.0, [150.0, 190.0, 255.0]),
+            (1.0, [255.0, 170.0, 140.0]),
+            (range, [180.0, 20.0, 20.0]),
+        ]);
+
+        let row_len = img.width() as usize * 4;
+        let cell_rows = row_len * cell_size as usize;
+        img.par_chunks_mut(cell_rows)
+            .enumerate()
+            .for_each(|(pixel_y, rows)| {
+                for pixel_x in 0..self.extent.width {
+                    let Some(change) = self.change(pixel_x, pixel_y as u32) else {
+                        continue;
+                    };
+                    for patch_z in 0..cell_size as usize {
+                        let start = patch_z * row_len + (pixel_x * cell_size) as usize * 4;
+                        for pixel in rows[start..start + cell_size as usize * 4].chunks_mut(4) {
+                            let [r, g, b] = match change {
+                                Change::Unchanged => grey([pixel[0], pixel[1], pixel[2]]),
+                                Change::Height(delta) => ramp.color_at(delta as f64).0,
+                                Change::Retextured => RETEXTURED_COLOR.0,
+                                Change::Added | Change::Removed => ONE_SIDED_COLOR.0,
+                            };
+                            let alpha = if change == Change::Unchanged {
+                                pixel[3]
+                            } else {
+                                255
+                            };
+                            pixel.copy_from_slice(&[r, g, b, alpha]);
+                        }
+                    }
+                }
+            });
+    }
+}
+
+/// The cell at world cell coordinates (`world_x`, `world_z`), unless its page
+/// is missing or no entry of the page sets it
+fn defined_cell(map: &ElevMap, world_x: i32, world_z: i32) -> Option<&ElevCell> {
+    map.get_world_cell(world_x, world_z)
+        .filter(|_| map.is_world_cell_defined(world_x, world_z))
+}
+
+/// The luma of a colour, lightened so that changes stand out against it
+fn grey([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let luma = 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+    let value = (64.0 + luma * 0.6).round() as u8;
+    [value; 3]
+}
+
+#[cfg(test)]
+mod tests {
+    use elev::{Orientation, Rotation};
+
+    use super::*;
+    use crate::extent::Region;
+
+    fn map_of(cells: &[(i32, i32)]) -> ElevMap {
+        let mut map = ElevMap::new();
+        for &(world_x, height) in cells {
+            let cell = ElevCell {
+                texture_id: 1,
+                rotation: Rotation::R0,
+                height,
+            };
+            map.set_world_cell(world_x, 0, cell);
+        }
+        map
+    }
+
+    fn summary(old: &ElevMap, new: &ElevMap, max_x: i32) -> DiffSummary {
+        let region = Region {
+            min_x: 0,
+            min_z: 0,
+            max_x,
+            max_z: 0,
+        };
+        let extent = Extent::of_region(&region, Orientation::default()).unwrap();
+        Diff { old, new, extent }.summary()
+    }
+
+    #[test]
+    fn cells_no_entry_sets_count_as_absent() {
+        // Both dumps have page 0, but only the new one sets cells 1 and 2
+        let old = map_of(&[(0, 10), (3, 0)]);
+        let new = map_of(&[(0, 10), (1, 0), (2, 5)]);
+        let summary = summary(&old, &new, 5);
+        assert_eq!(summary.unchanged, 1);
+        assert_eq!(summary.added, 2);
+        assert_eq!(summary.removed, 1);
+        assert_eq!(summary.raised + summary.lowered, 0);
+    }
+
+    #[test]
+    fn measures_changes_across_the_whole_height_range() {
+        let old = map_of(&[(0, i32::MIN), (1, i32::MAX)]);
+        let new = map_of(&[(0, i32::MAX), (1, i32::MIN)]);
+        let summary = summary(&old, &new, 1);
+        let span = i64::from(i32::MAX) - i64::from(i32::MIN);
+        assert_eq!((summary.raised, summary.max_raise), (1, span));
+        assert_eq!((summary.lowered, summary.max_lower), (1, span));
+    }
+}
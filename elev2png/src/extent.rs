@@ -0,0 +1,329 @@
+use std::str::FromStr;
+
+use elev::{ElevMap, Orientation};
+
+/// Most bytes an image drawn whole in memory may take, at four per pixel.
+/// Streamed images are drawn a band at a time and may be larger.
+const MAX_IMAGE_BYTES: u128 = 1 << 32;
+
+/// The part of the world drawn in an image, one pixel per cell before any
+/// scaling, and which way round it is drawn
+#[derive(Debug, Clone, Copy)]
+pub struct Extent {
+    /// World cell coordinates of the cell drawn at pixel (0, 0)
+    pub origin_world_x: i32,
+    pub origin_world_z: i32,
+    /// Size in cells
+    pub width: u32,
+    pub height: u32,
+    pub orientation: Orientation,
+}
+
+impl Extent {
+    /// Every page of the map
+    pub fn of_map(elev_map: &ElevMap, orientation: Orientation) -> Result<Self, String> {
+        Self::of_maps(&[elev_map], orientation)
+    }
+
+    /// Every page of any of the maps
+    pub fn of_maps(elev_maps: &[&ElevMap], orientation: Orientation) -> Result<Self, String> {
+        let bounds = elev_maps
+            .iter()
+            .map(|map| map.get_bounds())
+            .fold((i32::MAX, i32::MAX, i32::MIN, i32::MIN), union_bounds);
+        Self::of_bounds(bounds, orientation)
+    }
+
+    /// Every page within page bounds `(min_x, min_z, max_x, max_z)`, as
+    /// given by [`ElevMap::get_bounds`]
+    pub fn of_bounds(
+        bounds: (i32, i32, i32, i32),
+        orientation: Orientation,
+    ) -> Result<Self, String> {
+        let (min_x, min_z, max_x, max_z) = bounds;
+        if min_x > max_x || min_z > max_z {
+            return Err("There are no pages to draw".to_string());
+        }
+
+        let first_cell = |page: i32| page.checked_mul(128);
+        let last_cell = |page: i32| page.checked_mul(128)?.checked_add(127);
+        let (Some(min_x), Some(min_z), Some(max_x), Some(max_z)) = (
+            first_cell(min_x),
+            first_cell(min_z),
+            last_cell(max_x),
+            last_cell(max_z),
+        ) else {
+            return Err(format!(
+                "Pages {min_x}, {min_z} to {max_x}, {max_z} lie beyond the world cell coordinates"
+            ));
+        };
+
+        let region = Region {
+            min_x,
+            min_z,
+            max_x,
+            max_z,
+        };
+        Self::of_region(&region, orientation)
+    }
+
+    pub fn of_region(region: &Region, orientation: Orientation) -> Result<Self, String> {
+        let cells = |min: i32, max: i32| u32::try_from(i64::from(max) - i64::from(min) + 1);
+        let (Ok(cells_x), Ok(cells_z)) = (
+            cells(region.min_x, region.max_x),
+            cells(region.min_z, region.max_z),
+        ) else {
+            return Err(format!(
+                "The region from {}, {} to {}, {} is too large to draw",
+                region.min_x, region.min_z, region.max_x, region.max_z
+            ));
+        };
+
+        let (origin_world_x, origin_world_z) =
+            orientation.origin((region.min_x, region.min_z), (region.max_x, region.max_z));
+        let (width, height) = orientation.image_size(cells_x, cells_z);
+        Ok(Extent {
+            origin_world_x,
+            origin_world_z,
+            width,
+            height,
+            orientation,
+        })
+    }
+
+    /// World cell coordinates of the cell at a pixel
+    pub fn world_cell(&self, pixel_x: u32, pixel_y: u32) -> (i32, i32) {
+        self.orientation
+            .world_cell((self.origin_world_x, self.origin_world_z), pixel_x, pixel_y)
+    }
+
+    /// Size in pixels of an image drawing each cell as `cell_size` pixels
+    /// square, if it can be addressed
+    pub fn scaled_size(&self, cell_size: u32) -> Result<(u32, u32), String> {
+        match (
+            self.width.checked_mul(cell_size),
+            self.height.checked_mul(cell_size),
+        ) {
+            (Some(width), Some(height)) => Ok((width, height)),
+            _ => Err(format!(
+                "An image of {}×{} cells at {cell_size} pixels per cell is too large",
+                self.width, self.height
+            )),
+        }
+    }
+
+    /// [`Extent::scaled_size`], if an image of that size can also be held in
+    /// memory to be drawn whole
+    pub fn image_size(&self, cell_size: u32) -> Result<(u32, u32), String> {
+        let (width, height) = self.scaled_size(cell_size)?;
+        let bytes = u128::from(width) * u128::from(height) * 4;
+        if bytes > MAX_IMAGE_BYTES.min(isize::MAX as u128) {
+            return Err(format!(
+                "An image of {}×{} cells at {cell_size} pixels per cell is too large to draw \
+                 in memory; --stream draws it a band at a time",
+                self.width, self.height
+            ));
+        }
+        Ok((width, height))
+    }
+}
+
+/// The page bounds covering both `a` and `b`
+pub fn union_bounds(a: (i32, i32, i32, i32), b: (i32, i32, i32, i32)) -> (i32, i32, i32, i32) {
+    (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3))
+}
+
+/// A rectangle of world cells, inclusive of its edges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub min_x: i32,
+    pub min_z: i32,
+    pub max_x: i32,
+    pub max_z: i32,
+}
+
+/// Parses either two corners in AW coordinates separated by a colon, such as
+/// "12N 34W:56S 78E", or four world cell coordinates "x1,z1,x2,z2".
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ((x1, z1), (x2, z2)) = match s.split_once(':') {
+            Some((first, second)) => (parse_aw_coordinates(first)?, parse_aw_coordinates(second)?),
+            None => {
+                let values = s
+                    .split(',')
+                    .map(|value| value.trim().parse::<i32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("Invalid world cell coordinate: {e}"))?;
+                let &[x1, z1, x2, z2] = values.as_slice() else {
+                    return Err(format!(
+                        "Expected four world cell coordinates, got {}",
+                        values.len()
+                    ));
+                };
+                ((x1, z1), (x2, z2))
+            }
+        };
+
+        Ok(Region {
+            min_x: x1.min(x2),
+            min_z: z1.min(z2),
+            max_x: x1.max(x2),
+            max_z: z1.max(z2),
+        })
+    }
+}
+
+/// Parses AW coordinates such as "12N 34W" or "0.5s 7e" into the world cell
+/// that contains them
+fn parse_aw_coordinates(s: &str) -> Result<(i32, i32), String> {
+    let mut world_x = None;
+    let mut world_z = None;
+    let mut number = String::new();
+
+    for c in s.chars().filter(|c| !c.is_whitespace()) {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+
+        let value: f64 = number
+            .parse()
+            .map_err(|_| format!("Invalid AW coordinates {s:?}"))?;
+        number.clear();
+        let (axis, value) = match c.to_ascii_uppercase() {
+            'N' => (&mut world_z, value),
+            'S' => (&mut world_z, -value),
+            'W' => (&mut world_x, value),
+            'E' => (&mut world_x, -value),
+            _ => return Err(format!("Unexpected {c:?} in AW coordinates {s:?}")),
+        };
+        let cell = value.floor();
+        if !(f64::from(i32::MIN)..=f64::from(i32::MAX)).contains(&cell) {
+            return Err(format!("AW coordinates {s:?} lie beyond the world"));
+        }
+        if axis.replace(cell as i32).is_some() {
+            return Err(format!("Repeated direction in AW coordinates {s:?}"));
+        }
+    }
+
+    match (world_x, world_z) {
+        (Some(world_x), Some(world_z)) if number.is_empty() => Ok((world_x, world_z)),
+        _ => Err(format!(
+            "Expected AW coordinates such as \"12N 34W\", got {s:?}"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use elev::Direction;
+
+    use super::*;
+
+    #[test]
+    fn covers_whole_pages() {
+        let orientation = Orientation::new(Direction::East, false);
+        let extent = Extent::of_bounds((-1, 0, 1, 0), orientation).unwrap();
+        assert_eq!((extent.width, extent.height), (128, 384));
+        assert_eq!(extent.world_cell(0, 0), (-128, 127));
+        assert_eq!(extent.world_cell(127, 383), (255, 0));
+    }
+
+    #[test]
+    fn covers_regions_cell_by_cell() {
+        let region = Region {
+            min_x: -5,
+            min_z: 10,
+            max_x: 4,
+            max_z: 12,
+        };
+        let extent = Extent::of_region(&region, Orientation::default()).unwrap();
+        assert_eq!((extent.width, extent.height), (10, 3));
+        assert_eq!(extent.world_cell(0, 0), (4, 12));
+        assert_eq!(extent.world_cell(9, 2), (-5, 10));
+        assert_eq!(extent.scaled_size(4), Ok((40, 12)));
+    }
+
+    fn region(min_x: i32, min_z: i32, max_x: i32, max_z: i32) -> Region {
+        Region {
+            min_x,
+            min_z,
+            max_x,
+            max_z,
+        }
+    }
+
+    #[test]
+    fn parses_regions_in_aw_coordinates() {
+        assert_eq!("12N 34W:56S 78E".parse(), Ok(region(-78, -56, 34, 12)));
+        assert_eq!("56s78e : 12n34w".parse(), Ok(region(-78, -56, 34, 12)));
+        // Cells contain the coordinates, rounding towards the south east
+        assert_eq!("0.5S 7.5E:0.5N 0W".parse(), Ok(region(-8, -1, 0, 0)));
+    }
+
+    #[test]
+    fn parses_regions_in_world_cells() {
+        assert_eq!("1,2,-3,4".parse(), Ok(region(-3, 2, 1, 4)));
+        assert_eq!(" 5 , -6,5,-6".parse(), Ok(region(5, -6, 5, -6)));
+    }
+
+    #[test]
+    fn rejects_malformed_regions() {
+        for (s, error) in [
+            ("1,2,3", "Expected four world cell coordinates, got 3"),
+            ("1,2,x,4", "Invalid world cell coordinate"),
+            ("12N:34W", "Expected AW coordinates"),
+            ("12N 34W 5:0N 0W", "Expected AW coordinates"),
+            ("12N 34N:0N 0W", "Repeated direction"),
+            ("12Q 34W:0N 0W", "Unexpected 'Q'"),
+            ("N 34W:0N 0W", "Invalid AW coordinates"),
+            ("1.2.3N 4W:0N 0W", "Invalid AW coordinates"),
+            ("3000000000N 0W:0N 0W", "beyond the world"),
+        ] {
+            let why = s.parse::<Region>().unwrap_err();
+            assert!(why.contains(error), "{s:?}: {why}");
+        }
+    }
+
+    #[test]
+    fn rejects_what_cannot_be_drawn() {
+        let empty = Extent::of_map(&ElevMap::new(), Orientation::default());
+        assert!(empty.unwrap_err().contains("no pages"));
+
+        let beyond = Extent::of_bounds((0, 0, i32::MAX / 128 + 1, 0), Orientation::default());
+        assert!(beyond.unwrap_err().contains("beyond"));
+
+        let everything = Region {
+            min_x: i32::MIN,
+            min_z: 0,
+            max_x: i32::MAX,
+            max_z: 0,
+        };
+        let too_wide = Extent::of_region(&everything, Orientation::default());
+        assert!(too_wide.unwrap_err().contains("too large"));
+
+        let region = Region {
+            max_x: i32::MAX - 1,
+            ..everything
+        };
+        let extent = Extent::of_region(&region, Orientation::default()).unwrap();
+        assert_eq!(extent.width, u32::MAX);
+        assert!(extent.scaled_size(2).unwrap_err().contains("too large"));
+    }
+
+    #[test]
+    fn rejects_images_too_large_to_hold_in_memory() {
+        let region: Region = "0,0,59999,59999".parse().unwrap();
+        let extent = Extent::of_region(&region, Orientation::default()).unwrap();
+        assert_eq!(extent.scaled_size(1), Ok((60000, 60000)));
+        assert!(extent.image_size(1).unwrap_err().contains("too large"));
+
+        // A gigapixel still fits
+        let region: Region = "0,0,32767,32767".parse().unwrap();
+        let extent = Extent::of_region(&region, Orientation::default()).unwrap();
+        assert_eq!(extent.image_size(1), Ok((32768, 32768)));
+        assert!(extent.image_size(2).is_err());
+    }
+}
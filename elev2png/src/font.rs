@@ -0,0 +1,114 @@
+use image::{Pixel, Rgb, RgbaImage};
+
+/// Width and height in pixels of each glyph of the font, before scaling
+pub const GLYPH_WIDTH: u32 = 5;
+pub const GLYPH_HEIGHT: u32 = 7;
+
+/// A 5×7 bitmap font covering what map labels and captions need: digits,
+/// capital letters, units and a few signs. Lowercase letters other than the
+/// units are drawn as capitals. Each row's bits run from the left at bit 4.
+fn glyph(c: char) -> Option<[u8; 7]> {
+    Some(match c {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x19, 0x15, 0x13, 0x11, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        'm' => [0x00, 0x00, 0x1A, 0x15, 0x15, 0x11, 0x11],
+        'k' => [0x10, 0x10, 0x12, 0x14, 0x18, 0x14, 0x12],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        ' ' => [0x00; 7],
+        c if c.is_ascii_lowercase() => return glyph(c.to_ascii_uppercase()),
+        _ => return None,
+    })
+}
+
+/// Width in pixels of `text` drawn at `scale`, including a column of
+/// spacing between glyphs
+pub fn text_width(text: &str, scale: u32) -> u32 {
+    let count = text.chars().count() as u32;
+    (count * (GLYPH_WIDTH + 1)).saturating_sub(1) * scale
+}
+
+/// Draws `text` with its top left corner at (`x`, `y`), surrounded by a one
+/// pixel outline so that it stays legible over any terrain. Characters the
+/// font lacks are drawn as blanks, and pixels outside the image are skipped.
+pub fn draw_text(
+    img: &mut RgbaImage,
+    text: &str,
+    x: i64,
+    y: i64,
+    scale: u32,
+    color: Rgb<u8>,
+    outline: Rgb<u8>,
+) {
+    let put = |img: &mut RgbaImage, px: i64, py: i64, color: Rgb<u8>| {
+        if px >= 0 && py >= 0 && px < img.width() as i64 && py < img.height() as i64 {
+            img.put_pixel(px as u32, py as u32, color.to_rgba());
+        }
+    };
+
+    let scale = i64::from(scale);
+    let lit_pixels = || {
+        text.chars().enumerate().flat_map(move |(index, c)| {
+            let rows = glyph(c).unwrap_or([0; 7]);
+            let left = x + index as i64 * (i64::from(GLYPH_WIDTH) + 1) * scale;
+            (0..GLYPH_HEIGHT as i64).flat_map(move |row| {
+                (0..GLYPH_WIDTH as i64)
+                    .filter(move |column| rows[row as usize] & (0x10 >> column) != 0)
+                    .flat_map(move |column| {
+                        (0..scale * scale).map(move |i| {
+                            (
+                                left + column * scale + i % scale,
+                                y + row * scale + i / scale,
+                            )
+                        })
+                    })
+            })
+        })
+    };
+
+    for (px, py) in lit_pixels() {
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            put(img, px + dx, py + dy, outline);
+        }
+    }
+    for (px, py) in lit_pixels() {
+        put(img, px, py, color);
+    }
+}
@@ -0,0 +1,348 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use image::Rgb;
+
+/// A colour ramp over heights, linearly interpolated between stops.
+///
+/// Gradient files are either GMT colour palette tables (`.cpt`), whose lines
+/// are `z0 color0 z1 color1`, optionally followed by an `L`, `U` or `B`
+/// annotation flag and a `;label`, or plain lists of `height color` stops.
+/// Colours are written `r g b`, `r/g/b`, `h-s-v`, `#rrggbb` or as one of the
+/// common X11 colour names such as `red`, `darkgreen` or `skyblue`; a
+/// `# COLOR_MODEL = HSV` line makes `h s v` triples hue, saturation and value
+/// instead. Blank lines, `#` comments and the GMT `B`, `F` and `N` lines are
+/// ignored. CMYK colours, grey levels given as a single number and colour
+/// patterns are not supported.
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    stops: Vec<(f64, [f64; 3])>,
+}
+
+impl Gradient {
+    pub fn new(mut stops: Vec<(f64, [f64; 3])>) -> Self {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Gradient { stops }
+    }
+
+    /// A conventional hypsometric tint for AW heights in cm, from coastal
+    /// green through browns to snow
+    pub fn hypsometric() -> Self {
+        Gradient::new(vec![
+            (0.0, [52.0, 130.0, 60.0]),
+            (1000.0, [120.0, 170.0, 80.0]),
+            (2500.0, [220.0, 210.0, 130.0]),
+            (5000.0, [170.0, 120.0, 70.0]),
+            (8000.0, [130.0, 110.0, 100.0]),
+            (10000.0, [250.0, 250.0, 250.0]),
+        ])
+    }
+
+    /// Green for flat ground through yellow to red at `max` degrees
+    pub fn slope(max: f64) -> Self {
+        Gradient::new(vec![
+            (0.0, [40.0, 160.0, 60.0]),
+            (max / 2.0, [240.0, 220.0, 60.0]),
+            (max, [200.0, 30.0, 30.0]),
+        ])
+    }
+
+    /// Blue for cells set by one entry, through green and yellow to red for
+    /// cells set by eight or more, and dark grey for cells no entry set
+    pub fn overwrites() -> Self {
+        Gradient::new(vec![
+            (0.0, [50.0, 50.0, 50.0]),
+            (1.0, [40.0, 70.0, 200.0]),
+            (2.0, [60.0, 180.0, 80.0]),
+            (4.0, [240.0, 220.0, 50.0]),
+            (8.0, [220.0, 40.0, 30.0]),
+        ])
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        let mut model = ColorModel::Rgb;
+        let mut stops = Vec::new();
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if let Some(comment) = line.strip_prefix('#') {
+                if let Some(("COLOR_MODEL", value)) =
+                    comment.split_once('=').map(|(k, v)| (k.trim(), v.trim()))
+                {
+                    model = ColorModel::from_name(value)
+                        .ok_or_else(|| format!("Unsupported colour model {value:?}"))?;
+                }
+                continue;
+            }
+
+            // Drop the ";label" annotation
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() || line.starts_with(['B', 'F', 'N']) {
+                continue;
+            }
+
+            parse_line(line, model, &mut stops)
+                .map_err(|why| format!("Invalid gradient line {line:?}: {why}"))?;
+        }
+
+        if stops.is_empty() {
+            return Err("Gradient has no colour stops".into());
+        }
+
+        Ok(Gradient::new(stops))
+    }
+
+    pub fn color_at(&self, value: f64) -> Rgb<u8> {
+        let to_rgb = |c: [f64; 3]| Rgb(c.map(|v| v.round().clamp(0.0, 255.0) as u8));
+
+        let Some(&(first_z, first)) = self.stops.first() else {
+            return Rgb([0, 0, 0]);
+        };
+        if value <= first_z {
+            return to_rgb(first);
+        }
+
+        for pair in self.stops.windows(2) {
+            let ((z0, c0), (z1, c1)) = (pair[0], pair[1]);
+            if value <= z1 {
+                let t = if z1 > z0 {
+                    (value - z0) / (z1 - z0)
+                } else {
+                    1.0
+                };
+                return to_rgb([0, 1, 2].map(|i| c0[i] + (c1[i] - c0[i]) * t));
+            }
+        }
+
+        to_rgb(self.stops[self.stops.len() - 1].1)
+    }
+}
+
+/// How the colour triples of a gradient file are meant
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColorModel {
+    Rgb,
+    Hsv,
+}
+
+impl ColorModel {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.trim_start_matches('+').to_ascii_uppercase().as_str() {
+            "RGB" => Some(ColorModel::Rgb),
+            "HSV" => Some(ColorModel::Hsv),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the one or two stops of a gradient line, skipping a trailing
+/// `L`, `U` or `B` annotation flag and `#` comment
+fn parse_line(
+    line: &str,
+    model: ColorModel,
+    stops: &mut Vec<(f64, [f64; 3])>,
+) -> Result<(), String> {
+    let mut tokens = line
+        .split_whitespace()
+        .take_while(|token| !token.starts_with('#') || hex_color(token).is_some())
+        .peekable();
+
+    let mut count = 0;
+    while let Some(token) = tokens.next() {
+        if count > 0 && matches!(token, "L" | "U" | "B") && tokens.peek().is_none() {
+            break;
+        }
+        let z = parse_number(token)?;
+        let color = parse_color(&mut tokens, model)?;
+        stops.push((z, color));
+        count += 1;
+    }
+
+    match count {
+        1 | 2 => Ok(()),
+        _ => Err("expected one or two colour stops".to_string()),
+    }
+}
+
+fn parse_number(token: &str) -> Result<f64, String> {
+    token
+        .parse()
+        .map_err(|e| format!("{token:?} is not a number: {e}"))
+}
+
+/// Parses the colour starting at the next token, as 0–255 RGB components
+fn parse_color<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+    model: ColorModel,
+) -> Result<[f64; 3], String> {
+    let token = tokens.next().ok_or("missing colour")?;
+    let triple = |parts: Vec<&str>| -> Result<[f64; 3], String> {
+        match parts[..] {
+            [a, b, c] => Ok([parse_number(a)?, parse_number(b)?, parse_number(c)?]),
+            _ => Err(format!("{token:?} is not a colour")),
+        }
+    };
+
+    if token.starts_with('#') {
+        hex_color(token).ok_or_else(|| format!("{token:?} is not a colour"))
+    } else if token.contains('/') {
+        triple(token.split('/').collect())
+    } else if token.trim_start_matches('-').contains('-') {
+        let [h, s, v] = triple(token.split('-').collect())?;
+        Ok(hsv_to_rgb(h, s, v))
+    } else if token.starts_with(char::is_alphabetic) {
+        named_color(token).ok_or_else(|| format!("unknown colour name {token:?}"))
+    } else {
+        let second = tokens.next().ok_or("missing colour component")?;
+        let third = tokens.next().ok_or("missing colour component")?;
+        let [a, b, c] = triple(vec![token, second, third])?;
+        Ok(match model {
+            ColorModel::Rgb => [a, b, c],
+            ColorModel::Hsv => hsv_to_rgb(a, b, c),
+        })
+    }
+}
+
+/// Parses a `#rrggbb` colour
+fn hex_color(token: &str) -> Option<[f64; 3]> {
+    let hex = token.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([16, 8, 0].map(|shift| f64::from((value >> shift) & 0xff)))
+}
+
+/// Converts a hue in degrees and a saturation and value from 0 to 1
+fn hsv_to_rgb(h: f64, s: f64, v: f64) -> [f64; 3] {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let c = v * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = v - c;
+    [r, g, b].map(|component| (component + m) * 255.0)
+}
+
+/// The X11 colours GMT palettes most often name
+fn named_color(name: &str) -> Option<[f64; 3]> {
+    let rgb: [u8; 3] = match name.to_ascii_lowercase().as_str() {
+        "black" => [0, 0, 0],
+        "white" => [255, 255, 255],
+        "red" => [255, 0, 0],
+        "green" => [0, 255, 0],
+        "blue" => [0, 0, 255],
+        "yellow" => [255, 255, 0],
+        "cyan" => [0, 255, 255],
+        "magenta" => [255, 0, 255],
+        "gray" | "grey" => [190, 190, 190],
+        "darkgray" | "darkgrey" => [169, 169, 169],
+        "lightgray" | "lightgrey" => [211, 211, 211],
+        "orange" => [255, 165, 0],
+        "brown" => [165, 42, 42],
+        "purple" => [160, 32, 240],
+        "pink" => [255, 192, 203],
+        "navy" => [0, 0, 128],
+        "darkblue" => [0, 0, 139],
+        "lightblue" => [173, 216, 230],
+        "skyblue" => [135, 206, 235],
+        "darkgreen" => [0, 100, 0],
+        "forestgreen" => [34, 139, 34],
+        "lightgreen" => [144, 238, 144],
+        "seagreen" => [46, 139, 87],
+        "olivedrab" => [107, 142, 35],
+        "darkred" => [139, 0, 0],
+        "maroon" => [176, 48, 96],
+        "tan" => [210, 180, 140],
+        "beige" => [245, 245, 220],
+        "wheat" => [245, 222, 179],
+        "khaki" => [240, 230, 140],
+        "gold" => [255, 215, 0],
+        "sienna" => [160, 82, 45],
+        "chocolate" => [210, 105, 30],
+        "snow" => [255, 250, 250],
+        _ => return None,
+    };
+    Some(rgb.map(f64::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, contents: &str) -> Result<Gradient, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!(
+            "elev2png-gradient-{}-{name}.cpt",
+            std::process::id()
+        ));
+        fs::write(&path, contents).unwrap();
+        let gradient = Gradient::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        gradient
+    }
+
+    #[test]
+    fn loads_height_lists() {
+        let gradient = load("list", "# height r g b\n0 0 0 0\n\n100 200 100 0 # top\n").unwrap();
+        assert_eq!(gradient.color_at(-5.0), Rgb([0, 0, 0]));
+        assert_eq!(gradient.color_at(50.0), Rgb([100, 50, 0]));
+        assert_eq!(gradient.color_at(500.0), Rgb([200, 100, 0]));
+    }
+
+    #[test]
+    fn loads_gmt_tables() {
+        let gradient = load(
+            "gmt",
+            "# GMT palette\n\
+             -100 0/0/255 0 #00ff00 L ;sea\n\
+             0 green 100 darkgreen B\n\
+             100 255 0 0 200 255 255 255 U\n\
+             B black\nF white\nN 128 128 128\n",
+        )
+        .unwrap();
+        assert_eq!(gradient.color_at(-100.0), Rgb([0, 0, 255]));
+        assert_eq!(gradient.color_at(0.0), Rgb([0, 255, 0]));
+        assert_eq!(gradient.color_at(99.9), Rgb([0, 100, 0]));
+        assert_eq!(gradient.color_at(200.0), Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn loads_hsv_tables() {
+        let gradient = load(
+            "hsv",
+            "#COLOR_MODEL = HSV\n0 0 1 1 10 120 1 0.5\n20 240-1-1 30 0-0-1\n",
+        )
+        .unwrap();
+        assert_eq!(gradient.color_at(0.0), Rgb([255, 0, 0]));
+        assert_eq!(gradient.color_at(10.0), Rgb([0, 128, 0]));
+        assert_eq!(gradient.color_at(20.0), Rgb([0, 0, 255]));
+        assert_eq!(gradient.color_at(30.0), Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_read() {
+        for (name, contents, error) in [
+            ("empty", "# nothing\n", "no colour stops"),
+            ("name", "0 chartreuse4\n", "unknown colour name"),
+            ("unicode", "0 é 1 é\n", "unknown colour name"),
+            ("short", "0 10 20\n", "missing colour component"),
+            ("long", "0 red 1 red 2 red\n", "one or two colour stops"),
+            (
+                "model",
+                "# COLOR_MODEL = CMYK\n",
+                "Unsupported colour model",
+            ),
+        ] {
+            let why = load(name, contents).unwrap_err().to_string();
+            assert!(why.contains(error), "{name}: {why}");
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::error::Error;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use elev::{ElevCell, ElevMap, Orientation};
+use image::{ImageBuffer, Luma};
+use serde::Serialize;
+use tiff::encoder::{colortype, TiffEncoder};
+
+use crate::extent::Extent;
+use crate::metadata::{self, Georeference};
+use crate::overlay::ImageLayout;
+
+/// Describes how to turn the pixels of an exported heightmap back into cell
+/// heights and world positions
+#[derive(Debug, Serialize)]
+pub struct HeightmapInfo {
+    #[serde(flatten)]
+    pub georeference: Georeference,
+    /// `height = offset + value * scale`
+    pub offset: f64,
+    pub scale: f64,
+    pub height_min: i32,
+    pub height_max: i32,
+    /// Value written for cells absent from the map or left undefined in their
+    /// page, which no height is written as
+    pub missing_value: u16,
+}
+
+/// Value the 16-bit heightmaps write for cells absent from the map or that
+/// no entry of their page covers, leaving 1 to 65535 for the heights
+pub const MISSING_VALUE: u16 = 0;
+
+/// Value written by the float exports for cells absent from the map or left
+/// undefined in their page
+pub const NODATA: f32 = -9999.0;
+
+/// Writes a 16-bit greyscale heightmap of the cells in `extent`, one pixel
+/// per cell, as a PNG or, for ".r16" and ".raw" outputs, as raw
+/// little-endian samples. Heights map to 1 through 65535 and missing cells
+/// to [`MISSING_VALUE`]. A JSON sidecar with the same name plus ".json"
+/// records the mapping back to heights.
+pub fn export_heightmap16(
+    elev_map: &ElevMap,
+    extent: Extent,
+    output: &Path,
+    height_min: i32,
+    height_max: i32,
+) -> Result<HeightmapInfo, Box<dyn Error>> {
+    let (width, height) = (extent.width, extent.height);
+    let range = (height_max as f64 - height_min as f64).max(1.0);
+    let scale = range / (u16::MAX - 1) as f64;
+    // Puts height_min at 1
+    let offset = height_min as f64 - scale;
+
+    let img: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_fn(width, height, |x, z| {
+        let (world_x, world_z) = extent.world_cell(x, z);
+        let value = defined_cell(elev_map, world_x, world_z).map_or(MISSING_VALUE, |cell| {
+            ((cell.height as f64 - offset) / scale)
+                .round()
+                .clamp(1.0, u16::MAX as f64) as u16
+        });
+        Luma([value])
+    });
+
+    match output.extension().and_then(|ext| ext.to_str()) {
+        Some("r16" | "raw") => {
+            let mut file = BufWriter::new(fs::File::create(output)?);
+            for value in img.as_raw() {
+                file.write_all(&value.to_le_bytes())?;
+            }
+            file.flush()?;
+        }
+        _ => img.save(output)?,
+    }
+
+    let info = HeightmapInfo {
+        georeference: cell_georeference(extent),
+        offset,
+        scale,
+        height_min,
+        height_max,
+        missing_value: MISSING_VALUE,
+    };
+    metadata::write_sidecar(output, &info)?;
+
+    Ok(info)
+}
+
+/// Writes the heights in metres of the cells in `extent` as a 32-bit float
+/// greyscale TIFF, one pixel per cell, with [`NODATA`] for missing cells
+pub fn export_heights_tiff(
+    elev_map: &ElevMap,
+    extent: Extent,
+    output: &Path,
+) -> Result<Georeference, Box<dyn Error>> {
+    let samples = heights_m(elev_map, extent);
+    let file = BufWriter::new(fs::File::create(output)?);
+    TiffEncoder::new(file)?.write_image::<colortype::Gray32Float>(
+        extent.width,
+        extent.height,
+        &samples,
+    )?;
+    Ok(cell_georeference(extent))
+}
+
+/// Writes the heights in metres of the cells in `extent` as an ESRI ASCII
+/// grid. The format can only hold north-up maps, whose lower left corner
+/// its header places in metres.
+pub fn export_ascii_grid(
+    elev_map: &ElevMap,
+    extent: Extent,
+    output: &Path,
+) -> Result<Georeference, Box<dyn Error>> {
+    if extent.orientation != Orientation::default() {
+        return Err("ASCII grids can only be written north-up and unmirrored".into());
+    }
+
+    let georeference = cell_georeference(extent);
+    let samples = heights_m(elev_map, extent);
+    let mut file = BufWriter::new(fs::File::create(output)?);
+    writeln!(file, "ncols {}", extent.width)?;
+    writeln!(file, "nrows {}", extent.height)?;
+    writeln!(file, "xllcorner {}", georeference.corner_easting_m)?;
+    writeln!(
+        file,
+        "yllcorner {}",
+        georeference.corner_northing_m - extent.height as f64 * georeference.cell_size_m
+    )?;
+    writeln!(file, "cellsize {}", georeference.cell_size_m)?;
+    writeln!(file, "NODATA_value {NODATA}")?;
+    for row in samples.chunks(extent.width as usize) {
+        let line: Vec<String> = row.iter().map(f32::to_string).collect();
+        writeln!(file, "{}", line.join(" "))?;
+    }
+    file.flush()?;
+    Ok(georeference)
+}
+
+/// Heights in metres of the cells in `extent`, row by row, converted from
+/// the centimetres they are stored in
+fn heights_m(elev_map: &ElevMap, extent: Extent) -> Vec<f32> {
+    (0..extent.height)
+        .flat_map(|z| (0..extent.width).map(move |x| (x, z)))
+        .map(|(x, z)| {
+            let (world_x, world_z) = extent.world_cell(x, z);
+            defined_cell(elev_map, world_x, world_z)
+                .map_or(NODATA, |cell| cell.height as f32 / 100.0)
+        })
+        .collect()
+}
+
+/// The cell at world cell coordinates (`world_x`, `world_z`), unless its page
+/// is missing or no entry of the page covers it
+fn defined_cell(elev_map: &ElevMap, world_x: i32, world_z: i32) -> Option<&ElevCell> {
+    elev_map
+        .get_world_cell(world_x, world_z)
+        .filter(|_| elev_map.is_world_cell_defined(world_x, world_z))
+}
+
+/// Places an export drawn at one pixel per cell
+fn cell_georeference(extent: Extent) -> Georeference {
+    let layout = ImageLayout {
+        extent,
+        cell_size: 1.0,
+    };
+    Georeference::new(&layout, extent.width, extent.height)
+}
+
+#[cfg(test)]
+mod tests {
+    use elev::{ElevCell, Rotation};
+
+    use super::*;
+    use crate::extent::Region;
+
+    #[test]
+    fn heightmap16_keeps_missing_cells_apart_from_the_lowest() {
+        let mut elev_map = ElevMap::new();
+        for (world_x, height) in [(0, -500), (2, 1500), (3, 0)] {
+            let cell = ElevCell {
+                texture_id: 0,
+                rotation: Rotation::default(),
+                height,
+            };
+            elev_map.set_world_cell(world_x, 0, cell);
+        }
+        // Page -1 is missing and no entry covers cell 1 of page 0
+        let region = Region {
+            min_x: -1,
+            min_z: 0,
+            max_x: 3,
+            max_z: 0,
+        };
+        let extent = Extent::of_region(&region, Orientation::default()).unwrap();
+        let output =
+            std::env::temp_dir().join(format!("elev2png-heightmap-{}.r16", std::process::id()));
+
+        let info = export_heightmap16(&elev_map, extent, &output, -500, 1500).unwrap();
+        let data = fs::read(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+        fs::remove_file(metadata::sidecar_path(&output)).unwrap();
+
+        assert_eq!(info.missing_value, MISSING_VALUE);
+        let height = |value: u16| (info.offset + f64::from(value) * info.scale).round();
+        for (pixel_x, pair) in (0..).zip(data.chunks_exact(2)) {
+            let value = u16::from_le_bytes([pair[0], pair[1]]);
+            match extent.world_cell(pixel_x, 0) {
+                (-1, 0) | (1, 0) => assert_eq!(value, MISSING_VALUE),
+                (0, 0) => assert_eq!((value, height(value)), (1, -500.0)),
+                (2, 0) => assert_eq!((value, height(value)), (u16::MAX, 1500.0)),
+                (3, 0) => assert_eq!(height(value), 0.0),
+                cell => panic!("unexpected cell {cell:?}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use elev::ElevMap;
+use image::Rgb;
+
+/// Height units per cell width; cells are 10 m across and heights are in cm
+const CELL_WIDTH: f64 = 1000.0;
+
+/// Lambertian hillshading of the terrain surface, lit by a distant sun.
+pub struct Hillshade {
+    /// Unit vector towards the sun in (east, north, up) space
+    sun: [f64; 3],
+    z_factor: f64,
+}
+
+impl Hillshade {
+    /// `azimuth` is measured in degrees clockwise from north and `altitude`
+    /// in degrees above the horizon. `z_factor` exaggerates heights.
+    pub fn new(azimuth: f64, altitude: f64, z_factor: f64) -> Self {
+        let (azimuth, altitude) = (azimuth.to_radians(), altitude.to_radians());
+        Hillshade {
+            sun: [
+                azimuth.sin() * altitude.cos(),
+                azimuth.cos() * altitude.cos(),
+                altitude.sin(),
+            ],
+            z_factor,
+        }
+    }
+
+    /// The illumination of a cell from 0 (unlit) to 1 (facing the sun)
+    pub fn shade(&self, map: &ElevMap, world_x: i32, world_z: i32) -> f64 {
+        let Some((dh_dx, dh_dz)) = surface_gradient(map, world_x, world_z) else {
+            return 0.0;
+        };
+
+        // World x increases westwards and z northwards
+        let slope_east = -dh_dx * self.z_factor;
+        let slope_north = dh_dz * self.z_factor;
+
+        let normal = [-slope_east, -slope_north, 1.0];
+        let length = (normal[0] * normal[0] + normal[1] * normal[1] + 1.0).sqrt();
+        let dot = normal.iter().zip(self.sun).map(|(n, s)| n * s).sum::<f64>() / length;
+        dot.max(0.0)
+    }
+}
+
+/// The rate of change of height along world x and z at a cell, using Horn's
+/// method over its eight neighbours. Neighbours on other pages are used when
+/// present; missing ones take the centre cell's height.
+pub fn surface_gradient(map: &ElevMap, world_x: i32, world_z: i32) -> Option<(f64, f64)> {
+    let page = map.get_page(world_x.div_euclid(128), world_z.div_euclid(128))?;
+    let (x, z) = (world_x.rem_euclid(128), world_z.rem_euclid(128));
+    let center = page.get_cell(x as u8, z as u8)?;
+    // Neighbours on the same page are read from it without a map lookup
+    let height = |dx: i32, dz: i32| {
+        let neighbour = if (0..128).contains(&(x + dx)) && (0..128).contains(&(z + dz)) {
+            page.get_cell((x + dx) as u8, (z + dz) as u8)
+        } else {
+            map.get_world_cell(world_x + dx, world_z + dz)
+        };
+        neighbour.unwrap_or(center).height as f64
+    };
+
+    let dh_dx = ((height(1, -1) + 2.0 * height(1, 0) + height(1, 1))
+        - (height(-1, -1) + 2.0 * height(-1, 0) + height(-1, 1)))
+        / (8.0 * CELL_WIDTH);
+    let dh_dz = ((height(-1, 1) + 2.0 * height(0, 1) + height(1, 1))
+        - (height(-1, -1) + 2.0 * height(0, -1) + height(1, -1)))
+        / (8.0 * CELL_WIDTH);
+
+    Some((dh_dx, dh_dz))
+}
+
+/// The steepness of a cell in degrees
+pub fn slope_degrees(map: &ElevMap, world_x: i32, world_z: i32) -> f64 {
+    surface_gradient(map, world_x, world_z)
+        .map(|(dh_dx, dh_dz)| dh_dx.hypot(dh_dz).atan().to_degrees())
+        .unwrap_or(0.0)
+}
+
+pub fn multiply(color: Rgb<u8>, shade: f64) -> Rgb<u8> {
+    Rgb(color
+        .0
+        .map(|c| (c as f64 * shade).round().clamp(0.0, 255.0) as u8))
+}
+
+pub fn grey(shade: f64) -> Rgb<u8> {
+    let value = (shade * 255.0).round().clamp(0.0, 255.0) as u8;
+    Rgb([value, value, value])
+}
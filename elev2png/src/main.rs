@@ -1,144 +1,902 @@
-use clap::Parser;
-use elev::{ElevDump, ElevMap};
-use image::{ImageBuffer, Rgb, RgbImage};
-use std::collections::HashMap;
+mod alpha;
+mod color_mode;
+mod diff;
+mod extent;
+mod font;
+mod gradient;
+mod heightmap;
+mod hillshade;
+mod metadata;
+mod overlay;
+mod overwrites;
+mod palette;
+mod render;
+mod stream;
+mod texture_catalog;
+mod texture_patches;
+mod tiles;
+mod timelapse;
+
+use clap::{Parser, ValueEnum};
+use color_mode::{ColorMode, ColorModeKind, DepthShading, TextureColoring};
+use diff::Diff;
+use elev::{Direction, ElevDump, ElevMap, Orientation, TextureResolver};
+use extent::{Extent, Region};
+use gradient::Gradient;
+use hillshade::Hillshade;
+use image::buffer::ConvertBuffer;
+use image::imageops::{self, FilterType};
+use image::{RgbImage, RgbaImage};
+use metadata::{Georeference, Sidecar};
+use overlay::{ImageLayout, Overlay};
+use overwrites::OverwriteCounts;
+use palette::Palette;
+use render::Renderer;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::BufWriter;
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use texture_catalog::{ColorSummary, TextureCatalog};
+use texture_patches::TexturePatches;
 
-fn load_textures() -> HashMap<u32, Rgb<u8>> {
-    let mut textures = HashMap::new();
-    for i in 0..=1023 {
-        // Assuming texture IDs go from 0 to 1023
-        let path = format!("data/terrain{}.jpg", i);
-        if let Ok(img) = image::open(&path) {
-            let avg_color = average_color(&img.to_rgb8());
-            textures.insert(i, avg_color);
-        }
+/// The lowest and highest heights of the cells entries of the map set
+fn height_range(elev_map: &ElevMap) -> (i32, i32) {
+    elev_map
+        .iter_pages()
+        .flat_map(|(_, page)| page.iter_cells().filter(|&(x, z, _)| page.is_defined(x, z)))
+        .fold((i32::MAX, i32::MIN), |(min, max), (_, _, cell)| {
+            (min.min(cell.height), max.max(cell.height))
+        })
+}
+
+/// Resizes a rendered map, averaging the pixels each one covers when
+/// shrinking it
+fn resize(mut img: RgbaImage, width: u32, height: u32) -> RgbaImage {
+    if (width, height) == img.dimensions() {
+        return img;
     }
-    textures
+
+    alpha::premultiply(&mut img);
+    let mut img = if width <= img.width() && height <= img.height() {
+        imageops::thumbnail(&img, width, height)
+    } else {
+        imageops::resize(&img, width, height, FilterType::CatmullRom)
+    };
+    alpha::unpremultiply(&mut img);
+    img
 }
 
-fn average_color(img: &RgbImage) -> Rgb<u8> {
-    let (r, g, b) = img.pixels().fold((0u64, 0u64, 0u64), |acc, pixel| {
-        (
-            acc.0 + pixel[0] as u64,
-            acc.1 + pixel[1] as u64,
-            acc.2 + pixel[2] as u64,
-        )
-    });
-    let pixel_count = img.width() * img.height();
-    Rgb([
-        (r / pixel_count as u64) as u8,
-        (g / pixel_count as u64) as u8,
-        (b / pixel_count as u64) as u8,
-    ])
+/// Whether images of the format given by a file's extension can be saved
+/// with an alpha channel, which TIFFs can only when streamed
+fn supports_alpha(path: &Path, streamed: bool) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| match ext.to_ascii_lowercase().as_str() {
+            "png" | "webp" => true,
+            "tif" | "tiff" => streamed,
+            _ => false,
+        })
 }
 
-fn apply_depth(color: Rgb<u8>, height: i32, water_level: i32) -> Rgb<u8> {
-    // const WATER_LEVEL: i32 = 1900;
-    // const WATER_LEVEL: i32 = 1000;
-    const MAX_HEIGHT: f32 = 10_000.0;
-    const CONTRAST_CENTER: f32 = 2000.0;
-    const CONTRAST_WIDTH: f32 = 1000.0;
-
-    if height <= water_level {
-        // Underwater: use a brighter blue gradient
-        let depth_factor = (height as f32 / water_level as f32).sqrt();
-        let deep_water = Rgb([30, 30, 180]);
-        let shallow_water = Rgb([150, 150, 255]);
-
-        return Rgb([
-            ((shallow_water[0] as f32 * depth_factor + deep_water[0] as f32 * (1.0 - depth_factor))
-                as u8)
-                .max(30),
-            ((shallow_water[1] as f32 * depth_factor + deep_water[1] as f32 * (1.0 - depth_factor))
-                as u8)
-                .max(30),
-            ((shallow_water[2] as f32 * depth_factor + deep_water[2] as f32 * (1.0 - depth_factor))
-                as u8)
-                .max(180),
-        ]);
-    }
-
-    // Custom depth factor calculation to emphasize heights around 2000
-    let base_factor = (height as f32 - water_level as f32) / (MAX_HEIGHT - water_level as f32);
-    let contrast_factor =
-        1.0 / (1.0 + (-4.0 * (height as f32 - CONTRAST_CENTER) / CONTRAST_WIDTH).exp());
-    let depth_factor = (base_factor * 0.5 + contrast_factor * 0.5).min(1.0);
-
-    // Blend between 80% brightness and full color based on adjusted depth factor
-    let r = (color[0] as f32 * 0.6 + color[0] as f32 * 0.4 * depth_factor) as u8;
-    let g = (color[1] as f32 * 0.6 + color[1] as f32 * 0.4 * depth_factor) as u8;
-    let b = (color[2] as f32 * 0.6 + color[2] as f32 * 0.4 * depth_factor) as u8;
-
-    Rgb([r, g, b])
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// A colour image, in the format given by the output's extension
+    #[default]
+    Image,
+    /// A 16-bit greyscale heightmap as a PNG, or as raw little-endian samples
+    /// for ".r16" and ".raw" outputs, plus a JSON sidecar describing it.
+    /// Missing cells are 0 and heights 1 through 65535
+    Heightmap16,
+    /// Heights in metres as a 32-bit float greyscale TIFF
+    HeightsTiff,
+    /// Heights in metres as an ESRI ASCII grid, which must be north-up
+    Asc,
+    /// A directory of 256-pixel PNG tiles in a "z/x/y.png" pyramid, with an
+    /// "index.html" viewer to browse them
+    Tiles,
 }
 
 #[derive(Parser, Debug)]
 #[command(version, about)]
 struct Args {
-    /// The AW elevdump from which to make an image
+    /// The AW elevdump from which to make an image, or "-" to read it from
+    /// stdin. With --timelapse, a list of elevdumps instead.
     elevdump: PathBuf,
 
-    /// Directory containing textures in the form of "terrain#.jpg"
+    /// Directory or zip archive containing textures in the form of
+    /// "terrain#.jpg", or zipped as "terrain#.zip"
     texture_dir: PathBuf,
 
-    /// File to produce
+    /// An older elevdump to compare the elevdump with, drawing unchanged
+    /// terrain in grey, cells raised or lowered from red to blue, cells
+    /// whose texture or rotation changed in magenta and cells only one dump
+    /// has in yellow
+    #[arg(long, value_name = "OLD_ELEVDUMP")]
+    diff: Option<PathBuf>,
+
+    /// Change in height in centimetres drawn in the strongest colours of
+    /// --diff, defaulting to the largest change
+    #[arg(long, requires = "diff", value_parser = clap::value_parser!(u32).range(1..))]
+    diff_range: Option<u32>,
+
+    /// Animate a list of elevdumps, given in place of the elevdump as one
+    /// path per line optionally followed by a tab and a caption. Writes an
+    /// animated GIF or PNG for ".gif", ".png" and ".apng" outputs, or else
+    /// numbered PNGs in the output directory. Every frame covers the pages
+    /// of all the dumps and shares their textures and height range.
+    #[arg(long, conflicts_with_all = ["diff", "stream", "world_file", "sidecar", "entry_outlines"])]
+    timelapse: bool,
+
+    /// Write each frame's caption, or the name of its elevdump, in its top
+    /// left corner (timelapse)
+    #[arg(long, requires = "timelapse")]
+    captions: bool,
+
+    /// Milliseconds each frame is shown (timelapse)
+    #[arg(long, default_value_t = 500, requires = "timelapse")]
+    frame_delay: u16,
+
+    /// Texture file name pattern, in which "{id}" is replaced by the texture
+    /// id. Without an extension, jpg, jpeg, png, bmp and dds are tried.
+    #[arg(long, default_value = TextureResolver::DEFAULT_PATTERN)]
+    texture_pattern: String,
+
+    /// Which summary of each texture's colours to draw
+    #[arg(long, value_enum, default_value_t)]
+    texture_color: ColorSummary,
+
+    /// JSON file caching the colours of decoded textures between runs,
+    /// keyed by their paths, sizes and modification times. Defaults to
+    /// "elev2png/texture-colors.json" in the user's cache directory.
+    #[arg(long, value_name = "FILE")]
+    color_cache: Option<PathBuf>,
+
+    /// TOML or JSON file mapping texture ids to colours, overriding the
+    /// colours of the textures it lists
+    #[arg(long)]
+    palette: Option<PathBuf>,
+
+    /// Use only the palette's colours without loading any textures, in
+    /// which case texture_dir is ignored
+    #[arg(long, requires = "palette")]
+    palette_only: bool,
+
+    /// Write the colours of every texture in texture_dir to a TOML or JSON
+    /// palette file that can be edited and passed back with --palette
+    #[arg(long)]
+    export_palette: Option<PathBuf>,
+
+    /// File to produce, or "-" to write a PNG to stdout, or the directory in
+    /// which to write tiles
     output: PathBuf,
 
+    /// Kind of file to produce
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+
+    /// Draw only part of the world, given as two corners in AW coordinates
+    /// such as "12N 34W:56S 78E" or in world cells as "x1,z1,x2,z2"
+    #[arg(long, allow_hyphen_values = true)]
+    region: Option<Region>,
+
+    /// Leave areas without terrain transparent: missing pages, and cells of
+    /// pages that no entry covers. Only PNG and WebP images, streamed TIFFs
+    /// and tiles keep the transparency.
+    #[arg(long)]
+    transparent: bool,
+
+    /// Compass direction at the top of the image
+    #[arg(long, default_value_t)]
+    up: Direction,
+
+    /// Mirror the image left to right, putting east on the left when north
+    /// is up
+    #[arg(long)]
+    mirror: bool,
+
+    /// Draw and encode the image a band of rows at a time, so that memory use
+    /// doesn't grow with its height. Only PNG and TIFF images can be
+    /// streamed, and only without resizing or overlays.
+    #[arg(long, conflicts_with_all = [
+        "scale", "max_size", "page_borders", "grid", "labels", "north_arrow", "scale_bar", "diff",
+        "entry_outlines",
+    ])]
+    stream: bool,
+
+    /// Resize the image by this factor, averaging pixels when shrinking it
+    #[arg(long)]
+    scale: Option<f64>,
+
+    /// Shrink the image so that neither side is longer than this many pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_size: Option<u32>,
+
+    /// How to colour the map
+    #[arg(long, value_enum, default_value_t)]
+    color_mode: ColorModeKind,
+
     /// Water level
     #[arg(long)]
     water_level: Option<i32>,
+
+    /// Height at which texture colours reach full brightness (texture mode)
+    #[arg(long, default_value_t = 10_000.0)]
+    max_height: f32,
+
+    /// Height around which brightness varies the most (texture mode)
+    #[arg(long, default_value_t = 2000.0)]
+    contrast_center: f32,
+
+    /// Range of heights over which brightness varies the most (texture mode)
+    #[arg(long, default_value_t = 1000.0)]
+    contrast_width: f32,
+
+    /// Height drawn black, defaulting to the lowest cell (height mode and
+    /// heightmap16 format)
+    #[arg(long)]
+    height_min: Option<i32>,
+
+    /// Height drawn white, defaulting to the highest cell (height mode and
+    /// heightmap16 format)
+    #[arg(long)]
+    height_max: Option<i32>,
+
+    /// GMT .cpt or "height r g b" gradient file (hypsometric mode)
+    #[arg(long)]
+    gradient: Option<PathBuf>,
+
+    /// Slope in degrees drawn in the steepest colour (slope mode)
+    #[arg(long, default_value_t = 45.0)]
+    slope_max: f64,
+
+    /// Draw each cell as an N×N patch of its texture instead of a single
+    /// pixel of its average colour
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=256))]
+    cell_size: u32,
+
+    /// Shade the terrain by its slope as lit by the sun
+    #[arg(long)]
+    hillshade: bool,
+
+    /// Draw only the hillshade, in greyscale, without texture colours
+    #[arg(long)]
+    hillshade_only: bool,
+
+    /// Direction of the sun in degrees clockwise from north
+    #[arg(long, default_value_t = 315.0)]
+    sun_azimuth: f64,
+
+    /// Height of the sun in degrees above the horizon
+    #[arg(long, default_value_t = 45.0)]
+    sun_altitude: f64,
+
+    /// Exaggeration applied to heights when computing the hillshade
+    #[arg(long, default_value_t = 1.0)]
+    z_factor: f64,
+
+    /// Draw the borders between pages
+    #[arg(long)]
+    page_borders: bool,
+
+    /// Draw a coordinate grid with lines every N cells
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    grid: Option<u32>,
+
+    /// Label the grid lines, or the page borders without a grid, with AW
+    /// coordinates such as "12N 34W"
+    #[arg(long)]
+    labels: bool,
+
+    /// Draw an arrow pointing north in the top right corner
+    #[arg(long)]
+    north_arrow: bool,
+
+    /// Draw a scale bar in the bottom left corner
+    #[arg(long)]
+    scale_bar: bool,
+
+    /// Outline the square of cells each entry of the elevdump sets, coloured
+    /// from blue for small radii to red for large ones. Pair with
+    /// "--color-mode overwrites" to see how many entries set each cell.
+    #[arg(long)]
+    entry_outlines: bool,
+
+    /// Write a world file placing the output in metres, such as ".pgw" for
+    /// a PNG or ".tfw" for a TIFF. ASCII grids place themselves.
+    #[arg(long)]
+    world_file: bool,
+
+    /// Write a JSON sidecar with the output's name plus ".json" giving its
+    /// origin, orientation and cell size in metres. Heightmap16 outputs
+    /// always have one.
+    #[arg(long)]
+    sidecar: bool,
+}
+
+fn save_world_file(output: &Path, georeference: &Georeference) -> ExitCode {
+    match metadata::write_world_file(output, georeference) {
+        Ok(()) => {
+            println!(
+                "World file saved to {:?}",
+                metadata::world_file_path(output)
+            );
+            ExitCode::SUCCESS
+        }
+        Err(why) => {
+            eprintln!("Failed to save world file: {why}");
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn main() {
+/// Writes the world file and sidecar asked for alongside a colour image
+fn save_image_georeferencing(
+    args: &Args,
+    georeference: Georeference,
+    transparent: bool,
+) -> ExitCode {
+    let mut status = ExitCode::SUCCESS;
+    if args.world_file {
+        status = save_world_file(&args.output, &georeference);
+    }
+    if args.sidecar {
+        let sidecar = Sidecar {
+            georeference,
+            values: if transparent { "rgba" } else { "rgb" },
+            nodata: None,
+        };
+        if save_sidecar(&args.output, &sidecar) == ExitCode::FAILURE {
+            status = ExitCode::FAILURE;
+        }
+    }
+    status
+}
+
+fn save_sidecar(output: &Path, sidecar: &Sidecar) -> ExitCode {
+    match metadata::write_sidecar(output, sidecar) {
+        Ok(()) => {
+            println!("Sidecar saved to {:?}", metadata::sidecar_path(output));
+            ExitCode::SUCCESS
+        }
+        Err(why) => {
+            eprintln!("Failed to save sidecar: {why}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Loads what the chosen colour mode needs, including the textures among
+/// `texture_ids`, and exports the palette if asked. Heights are coloured over
+/// `height_range` unless given.
+/// `dump` is the elevdump being drawn, which only a single map has
+fn color_mode(
+    args: &Args,
+    texture_ids: &BTreeSet<u32>,
+    height_range: (i32, i32),
+    dump: Option<&ElevDump>,
+) -> Option<ColorMode> {
+    let palette = match &args.palette {
+        Some(path) => match Palette::from_file(path) {
+            Ok(palette) => palette,
+            Err(why) => {
+                eprintln!("Failed to load palette {path:?}: {why}");
+                return None;
+            }
+        },
+        None => Palette::default(),
+    };
+
+    let resolver = TextureResolver::new(&args.texture_dir).with_pattern(&args.texture_pattern);
+    let mut texture_ids: BTreeSet<u32> = texture_ids.iter().map(|tid| tid & 1023).collect();
+    // Textures the palette gives a colour for every rotation don't need to
+    // be loaded
+    for texture_id in palette.colored_texture_ids() {
+        texture_ids.remove(&texture_id);
+    }
+    // A hillshade alone draws no texture colours
+    let draw_textures = args.color_mode == ColorModeKind::Texture && !args.hillshade_only;
+    if args.palette_only || !draw_textures {
+        texture_ids.clear();
+    }
+    let draw_patches = args.cell_size > 1 && draw_textures;
+    let patch_ids = if draw_patches {
+        texture_ids.clone()
+    } else {
+        BTreeSet::new()
+    };
+
+    // An exported palette lists every texture of the source, so that it
+    // serves other dumps drawn with the same textures
+    if args.export_palette.is_some() {
+        match resolver.available_ids() {
+            Ok(available) => texture_ids.extend(available),
+            Err(why) => eprintln!("Failed to list textures in {:?}: {why}", resolver.source()),
+        }
+    }
+    let cache_path = args
+        .color_cache
+        .clone()
+        .or_else(texture_catalog::default_cache_path);
+    let mut textures =
+        TextureCatalog::load(&resolver, texture_ids, cache_path.as_deref(), &patch_ids);
+    let patches = draw_patches.then(|| TexturePatches::new(textures.take_images(), args.cell_size));
+
+    if let Some(path) = &args.export_palette {
+        let exported = Palette::from_catalog(&textures, args.texture_color);
+        match exported.to_file(path) {
+            Ok(()) => println!("Palette saved to {path:?}"),
+            Err(why) => {
+                eprintln!("Failed to save palette: {why}");
+                return None;
+            }
+        }
+    }
+
+    let mode = match args.color_mode {
+        ColorModeKind::Texture => ColorMode::Texture(TextureColoring {
+            palette,
+            catalog: textures,
+            patches,
+            summary: args.texture_color,
+            depth: DepthShading {
+                water_level: args.water_level.unwrap_or(0),
+                max_height: args.max_height,
+                contrast_center: args.contrast_center,
+                contrast_width: args.contrast_width,
+            },
+        }),
+        ColorModeKind::Height => ColorMode::Height {
+            min: args.height_min.unwrap_or(height_range.0),
+            max: args.height_max.unwrap_or(height_range.1),
+        },
+        ColorModeKind::Hypsometric => match &args.gradient {
+            Some(path) => match Gradient::from_file(path) {
+                Ok(gradient) => ColorMode::Hypsometric(gradient),
+                Err(why) => {
+                    eprintln!("Failed to load gradient {path:?}: {why}");
+                    return None;
+                }
+            },
+            None => ColorMode::Hypsometric(Gradient::hypsometric()),
+        },
+        ColorModeKind::Slope => ColorMode::Slope(Gradient::slope(args.slope_max)),
+        ColorModeKind::TextureId => ColorMode::TextureId,
+        ColorModeKind::Rotation => ColorMode::Rotation,
+        ColorModeKind::Overwrites => match dump {
+            Some(dump) => ColorMode::Overwrites {
+                counts: OverwriteCounts::of_dump(dump),
+                gradient: Gradient::overwrites(),
+            },
+            None => {
+                eprintln!("Overwrites can only be counted in a single elevdump");
+                return None;
+            }
+        },
+    };
+
+    Some(mode)
+}
+
+fn renderer<'a>(args: &Args, map: &'a ElevMap, mode: &'a ColorMode) -> Renderer<'a> {
+    Renderer {
+        map,
+        mode,
+        hillshade: (args.hillshade || args.hillshade_only)
+            .then(|| Hillshade::new(args.sun_azimuth, args.sun_altitude, args.z_factor)),
+        hillshade_only: args.hillshade_only,
+        cell_size: args.cell_size,
+    }
+}
+
+fn overlay(args: &Args) -> Overlay<'static> {
+    Overlay {
+        page_borders: args.page_borders,
+        grid: args.grid,
+        labels: args.labels,
+        north_arrow: args.north_arrow,
+        scale_bar: args.scale_bar,
+        caption: None,
+        entry_outlines: &[],
+    }
+}
+
+/// Makes a drawn map opaque unless it is `transparent`, resizes it as asked
+/// and draws the overlay on it
+fn finish_image(
+    mut img: RgbaImage,
+    args: &Args,
+    extent: Extent,
+    transparent: bool,
+    overlay: &Overlay,
+) -> (RgbaImage, ImageLayout) {
+    if !transparent {
+        // Undefined cells are drawn as they are stored, and missing pages black
+        alpha::make_opaque(&mut img);
+    }
+
+    let mut factor = args.scale.unwrap_or(1.0);
+    if let Some(max_size) = args.max_size {
+        let longest_side = img.width().max(img.height()) as f64 * factor;
+        factor *= (max_size as f64 / longest_side).min(1.0);
+    }
+    let scaled_size = |pixels: u32| ((pixels as f64 * factor).round() as u32).max(1);
+    let (width, height) = (scaled_size(img.width()), scaled_size(img.height()));
+    let mut img = resize(img, width, height);
+
+    let layout = ImageLayout {
+        extent,
+        cell_size: width as f64 / extent.width as f64,
+    };
+    if !overlay.is_empty() {
+        overlay.draw(&mut img, layout);
+    }
+    (img, layout)
+}
+
+/// Draws every elevdump of the list given with --timelapse as a frame
+fn timelapse(args: &Args) -> ExitCode {
+    let frames = match timelapse::read_list(&args.elevdump) {
+        Ok(frames) => frames,
+        Err(why) => {
+            eprintln!("Failed to read elevdump list {:?}: {why}", &args.elevdump);
+            return ExitCode::FAILURE;
+        }
+    };
+    let survey = match timelapse::survey(&frames) {
+        Ok(survey) => survey,
+        Err(why) => {
+            eprintln!("{why}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let orientation = Orientation::new(args.up, args.mirror);
+    let extent = match &args.region {
+        Some(region) => Extent::of_region(region, orientation),
+        None => Extent::of_bounds(survey.bounds, orientation),
+    };
+    let extent = match extent.and_then(|extent| {
+        extent.image_size(args.cell_size)?;
+        Ok(extent)
+    }) {
+        Ok(extent) => extent,
+        Err(why) => {
+            eprintln!("{why}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let Some(mode) = color_mode(args, &survey.texture_ids, survey.height_range, None) else {
+        return ExitCode::FAILURE;
+    };
+
+    let is_gif = args
+        .output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gif"));
+    if args.transparent && is_gif {
+        eprintln!("Only animated PNGs and PNG frames can be transparent");
+    }
+    let transparent = args.transparent && !is_gif;
+    let overlay = overlay(args);
+
+    let render_frame = |map: &ElevMap, frame: &timelapse::Frame| {
+        let img = renderer(args, map, &mode).render(extent);
+        let overlay = Overlay {
+            caption: args.captions.then(|| frame.caption.clone()),
+            ..overlay.clone()
+        };
+        finish_image(img, args, extent, transparent, &overlay).0
+    };
+    match timelapse::write(
+        &frames,
+        render_frame,
+        &args.output,
+        args.frame_delay,
+        transparent,
+    ) {
+        Ok(count) => {
+            println!("{count} frames saved to {:?}", &args.output);
+            ExitCode::SUCCESS
+        }
+        Err(why) => {
+            eprintln!("Failed to save timelapse: {why}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
 
-    let elevdump = match ElevDump::from_file(args.elevdump) {
+    if args.format != OutputFormat::Image && args.output == Path::new("-") {
+        eprintln!("Only images can be written to stdout");
+        return ExitCode::FAILURE;
+    }
+
+    if args.stream && args.format != OutputFormat::Image {
+        eprintln!("Only images can be streamed");
+        return ExitCode::FAILURE;
+    }
+
+    if args.diff.is_some() && args.format != OutputFormat::Image {
+        eprintln!("Differences can only be drawn as images");
+        return ExitCode::FAILURE;
+    }
+
+    if args.elevdump == Path::new("-") && args.diff.as_deref() == Some(Path::new("-")) {
+        eprintln!("Only one of the elevdumps can be read from stdin");
+        return ExitCode::FAILURE;
+    }
+
+    if args.timelapse && (args.format != OutputFormat::Image || args.output == Path::new("-")) {
+        eprintln!("Timelapses can only be written as image files");
+        return ExitCode::FAILURE;
+    }
+
+    if args
+        .scale
+        .is_some_and(|scale| !(scale > 0.0 && scale.is_finite()))
+    {
+        eprintln!("The scale must be a positive number");
+        return ExitCode::FAILURE;
+    }
+
+    if args.timelapse {
+        return timelapse(&args);
+    }
+
+    let elevdump = match ElevDump::from_file_or_stdin(&args.elevdump) {
         Ok(e) => e,
         Err(why) => {
             eprintln!("Failed importing elevdump: {why:#?}");
-            return;
+            return ExitCode::FAILURE;
         }
     };
 
     let elev_map = ElevMap::from(&elevdump);
-    let textures = load_textures();
 
-    let (min_x, min_z, max_x, max_z) = elev_map.get_bounds();
-    let width = (max_x - min_x + 1) * 128;
-    let height = (max_z - min_z + 1) * 128;
+    let old_map = match &args.diff {
+        Some(path) => match ElevDump::from_file_or_stdin(path) {
+            Ok(old_dump) => Some(ElevMap::from(&old_dump)),
+            Err(why) => {
+                eprintln!("Failed importing old elevdump: {why:#?}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let orientation = Orientation::new(args.up, args.mirror);
+    let extent = match (&args.region, &old_map) {
+        (Some(region), _) => Extent::of_region(region, orientation),
+        (None, Some(old_map)) => Extent::of_maps(&[&elev_map, old_map], orientation),
+        (None, None) => Extent::of_map(&elev_map, orientation),
+    };
+    let extent = match extent {
+        Ok(extent) => extent,
+        Err(why) => {
+            eprintln!("{why}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.format == OutputFormat::Heightmap16 {
+        let (min, max) = height_range(&elev_map);
+        let (min, max) = (
+            args.height_min.unwrap_or(min),
+            args.height_max.unwrap_or(max),
+        );
+        return match heightmap::export_heightmap16(&elev_map, extent, &args.output, min, max) {
+            Ok(info) => {
+                println!(
+                    "Heightmap saved to {:?} with sidecar {:?}",
+                    &args.output,
+                    metadata::sidecar_path(&args.output)
+                );
+                if args.world_file {
+                    return save_world_file(&args.output, &info.georeference);
+                }
+                ExitCode::SUCCESS
+            }
+            Err(why) => {
+                eprintln!("Failed to save heightmap: {why}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if matches!(args.format, OutputFormat::HeightsTiff | OutputFormat::Asc) {
+        let result = if args.format == OutputFormat::Asc {
+            heightmap::export_ascii_grid(&elev_map, extent, &args.output)
+        } else {
+            heightmap::export_heights_tiff(&elev_map, extent, &args.output)
+        };
+        return match result {
+            Ok(georeference) => {
+                println!("Heights saved to {:?}", &args.output);
+                let mut status = ExitCode::SUCCESS;
+                // ASCII grids are already placed by their header
+                if args.world_file && args.format == OutputFormat::HeightsTiff {
+                    status = save_world_file(&args.output, &georeference);
+                }
+                if args.sidecar {
+                    let sidecar = Sidecar {
+                        georeference,
+                        values: "height_m",
+                        nodata: Some(heightmap::NODATA as f64),
+                    };
+                    if save_sidecar(&args.output, &sidecar) == ExitCode::FAILURE {
+                        status = ExitCode::FAILURE;
+                    }
+                }
+                status
+            }
+            Err(why) => {
+                eprintln!("Failed to save heights: {why}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    // Only a streamed image needn't fit in memory
+    let size = if args.stream {
+        extent.scaled_size(args.cell_size)
+    } else {
+        extent.image_size(args.cell_size)
+    };
+    let (width, height) = match size {
+        Ok(size) => size,
+        Err(why) => {
+            eprintln!("{why}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let Some(mode) = color_mode(
+        &args,
+        &elev_map.texture_ids(),
+        height_range(&elev_map),
+        Some(&elevdump),
+    ) else {
+        return ExitCode::FAILURE;
+    };
 
-    let mut img = ImageBuffer::new(width as u32, height as u32);
+    let renderer = renderer(&args, &elev_map, &mode);
 
-    for (page_coords, _page) in elev_map.iter_pages() {
-        let page_x = page_coords.0;
-        let page_z = page_coords.1;
+    let transparent = args.transparent;
+    let writes_image_file = args.format == OutputFormat::Image && args.output != Path::new("-");
+    if transparent && writes_image_file && !supports_alpha(&args.output, args.stream) {
+        eprintln!("Only PNG and WebP images and streamed TIFFs can be transparent");
+    }
 
-        for x in 0..128u8 {
-            for z in 0..128u8 {
-                if let Some(cell) = elev_map.get_cell(page_x, page_z, x, z) {
-                    let pixel_x = (width - 1) as u32 - ((page_x - min_x) * 128 + x as i32) as u32;
-                    let pixel_z = (height - 1) as u32 - ((page_z - min_z) * 128 + z as i32) as u32;
+    if args.stream {
+        let transparent = transparent && (!writes_image_file || supports_alpha(&args.output, true));
+        if let Err(why) = stream::write_streamed(&renderer, extent, transparent, &args.output) {
+            eprintln!("Failed to save terrain map: {why}");
+            return ExitCode::FAILURE;
+        }
+        if !writes_image_file {
+            return ExitCode::SUCCESS;
+        }
+        println!("Terrain map saved to {:?}", &args.output);
+        let layout = ImageLayout {
+            extent,
+            cell_size: args.cell_size as f64,
+        };
+        return save_image_georeferencing(
+            &args,
+            Georeference::new(&layout, width, height),
+            transparent,
+        );
+    }
 
-                    let tid = cell.texture_id & 1023;
+    let mut img = renderer.render(extent);
 
-                    let base_color = textures.get(&tid).unwrap_or(&Rgb([0, 0, 0]));
+    if let Some(old_map) = &old_map {
+        let diff = Diff {
+            old: old_map,
+            new: &elev_map,
+            extent,
+        };
+        let summary = diff.summary();
+        let range = args.diff_range.unwrap_or_else(|| {
+            u32::try_from(summary.max_raise.max(summary.max_lower)).unwrap_or(u32::MAX)
+        });
+        diff.draw(&mut img, args.cell_size, range);
 
-                    // if !textures.contains_key(&tid) {
-                    //     println!("Does not contain {tid}");
-                    // }
+        let report = format!(
+            "Cells raised: {} (up to {} m), lowered: {} (up to {} m), retextured: {}, \
+             added: {}, removed: {}, unchanged: {}",
+            summary.raised,
+            summary.max_raise as f64 / 100.0,
+            summary.lowered,
+            summary.max_lower as f64 / 100.0,
+            summary.retextured,
+            summary.added,
+            summary.removed,
+            summary.unchanged,
+        );
+        // Keep stdout for the image when it is written there
+        if args.output == Path::new("-") {
+            eprintln!("{report}");
+        } else {
+            println!("{report}");
+        }
+    }
 
-                    let color =
-                        apply_depth(*base_color, cell.height, args.water_level.unwrap_or(0));
+    let overlay = Overlay {
+        entry_outlines: if args.entry_outlines {
+            &elevdump.entries
+        } else {
+            &[]
+        },
+        ..overlay(&args)
+    };
+    let (img, layout) = finish_image(img, &args, extent, transparent, &overlay);
 
-                    img.put_pixel(pixel_x, pixel_z, color);
-                }
+    if args.format == OutputFormat::Tiles {
+        return match tiles::write_tiles(&img, &elev_map, layout, transparent, &args.output) {
+            Ok((info, tile_count)) => {
+                println!(
+                    "{tile_count} tiles over zoom levels 0 to {} saved to {:?}",
+                    info.max_zoom, &args.output
+                );
+                ExitCode::SUCCESS
+            }
+            Err(why) => {
+                eprintln!("Failed to save tiles: {why}");
+                ExitCode::FAILURE
             }
+        };
+    }
+
+    // PNGs record the orientation of the map, which other formats can't
+    let text = metadata::text_chunks(&layout);
+    if args.output == Path::new("-") {
+        if let Err(why) = metadata::write_png(&img, transparent, std::io::stdout().lock(), text) {
+            eprintln!("Failed to write terrain map: {why:?}");
+            return ExitCode::FAILURE;
         }
+        return ExitCode::SUCCESS;
     }
 
-    match img.save(Path::new(&args.output)) {
+    let is_png = args
+        .output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    let result = if is_png {
+        File::create(&args.output)
+            .map_err(Into::into)
+            .and_then(|file| metadata::write_png(&img, transparent, BufWriter::new(file), text))
+    } else if transparent && supports_alpha(&args.output, false) {
+        img.save(&args.output).map_err(Into::into)
+    } else {
+        ConvertBuffer::<RgbImage>::convert(&img)
+            .save(&args.output)
+            .map_err(Into::into)
+    };
+    match result {
         Ok(()) => println!("Terrain map saved to {:?}", &args.output),
-        Err(why) => eprintln!("Failed to save terrain map: {why:?}"),
+        Err(why) => {
+            eprintln!("Failed to save terrain map: {why:?}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let georeference = Georeference::new(&layout, img.width(), img.height());
+    save_image_georeferencing(&args, georeference, transparent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn height_range_ignores_cells_no_entry_sets() {
+        // Heights 300 to 500 on part of a page left otherwise flat at 0
+        let dump =
+            ElevDump::from_str("elevdump version 2\n0 0 4 4 1 1 4 1 300 400 500 300\n").unwrap();
+        assert_eq!(height_range(&ElevMap::from(&dump)), (300, 500));
     }
 }
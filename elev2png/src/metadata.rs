@@ -0,0 +1,249 @@
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use image::buffer::ConvertBuffer;
+use image::{RgbImage, RgbaImage};
+use serde::Serialize;
+
+use crate::overlay::{ImageLayout, CELL_METRES};
+
+/// Places the pixels of an output in the world, both in AW cells and in
+/// metres. Eastings grow to the east and northings to the north, so an
+/// easting is the world x coordinate negated.
+#[derive(Debug, Clone, Serialize)]
+pub struct Georeference {
+    pub width: u32,
+    pub height: u32,
+    /// World cell coordinates of the cell drawn at pixel (0, 0)
+    pub origin_world_x: i32,
+    pub origin_world_z: i32,
+    /// Change in world cell coordinates `(x, z)` per cell to the right
+    pub world_step_right: (i32, i32),
+    /// Change in world cell coordinates `(x, z)` per cell downwards
+    pub world_step_down: (i32, i32),
+    /// Which way up the map is drawn, such as "north-up"
+    pub orientation: String,
+    pub cell_size_m: f64,
+    pub pixel_size_m: f64,
+    /// Easting and northing in metres of the outer corner of pixel (0, 0)
+    pub corner_easting_m: f64,
+    pub corner_northing_m: f64,
+}
+
+impl Georeference {
+    pub fn new(layout: &ImageLayout, width: u32, height: u32) -> Self {
+        let extent = &layout.extent;
+        let (right, down) = (extent.orientation.right(), extent.orientation.down());
+        let cell_size_m = CELL_METRES as f64;
+        let pixel_size_m = cell_size_m / layout.cell_size;
+
+        // The origin cell's centre, less half a cell towards the image's
+        // right and bottom
+        let centre_easting = -(extent.origin_world_x as f64 + 0.5) * cell_size_m;
+        let centre_northing = (extent.origin_world_z as f64 + 0.5) * cell_size_m;
+        let half_cell = cell_size_m / 2.0;
+
+        Self {
+            width,
+            height,
+            origin_world_x: extent.origin_world_x,
+            origin_world_z: extent.origin_world_z,
+            world_step_right: right,
+            world_step_down: down,
+            orientation: extent.orientation.to_string(),
+            cell_size_m,
+            pixel_size_m,
+            corner_easting_m: centre_easting + (right.0 + down.0) as f64 * half_cell,
+            corner_northing_m: centre_northing - (right.1 + down.1) as f64 * half_cell,
+        }
+    }
+
+    /// The six lines of an ESRI world file, which map pixel columns and rows
+    /// to eastings and northings from the centre of the top left pixel
+    pub fn world_file(&self) -> String {
+        let (right, down) = (self.world_step_right, self.world_step_down);
+        let size = self.pixel_size_m;
+        let (a, d) = (-right.0 as f64 * size, right.1 as f64 * size);
+        let (b, e) = (-down.0 as f64 * size, down.1 as f64 * size);
+        let c = self.corner_easting_m + (a + b) / 2.0;
+        let f = self.corner_northing_m + (d + e) / 2.0;
+        [a, d, b, e, c, f]
+            .iter()
+            .map(|value| format!("{value}\n"))
+            .collect()
+    }
+}
+
+/// A JSON sidecar for an output, saying where it is and what its pixels hold
+#[derive(Debug, Serialize)]
+pub struct Sidecar {
+    #[serde(flatten)]
+    pub georeference: Georeference,
+    /// Such as "rgb" or "height_m"
+    pub values: &'static str,
+    /// Value written for cells absent from the map
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nodata: Option<f64>,
+}
+
+/// The output's path with ".json" appended
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+/// The output's path with the world file extension for its format: the
+/// first and last letters of its extension plus "w", as in ".pgw" for PNGs
+/// and ".tfw" for TIFFs
+pub fn world_file_path(output: &Path) -> PathBuf {
+    let ext = output
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+    let mut chars = ext.chars();
+    let world_ext = match (chars.next(), chars.next_back()) {
+        (Some(first), Some(last)) => format!("{first}{last}w"),
+        _ => format!("{ext}w"),
+    };
+    output.with_extension(world_ext)
+}
+
+pub fn write_sidecar<T: Serialize>(output: &Path, sidecar: &T) -> Result<(), Box<dyn Error>> {
+    fs::write(sidecar_path(output), serde_json::to_string_pretty(sidecar)?)?;
+    Ok(())
+}
+
+pub fn write_world_file(output: &Path, georeference: &Georeference) -> std::io::Result<()> {
+    fs::write(world_file_path(output), georeference.world_file())
+}
+
+/// Records where the cells of a map are in an image as PNG text chunks, so
+/// that other tools can line it up with the world
+pub fn text_chunks(layout: &ImageLayout) -> Vec<(String, String)> {
+    let extent = &layout.extent;
+    let (right, down) = (extent.orientation.right(), extent.orientation.down());
+    [
+        ("Orientation", extent.orientation.to_string()),
+        (
+            "OriginWorldCell",
+            format!("{} {}", extent.origin_world_x, extent.origin_world_z),
+        ),
+        ("WorldStepRight", format!("{} {}", right.0, right.1)),
+        ("WorldStepDown", format!("{} {}", down.0, down.1)),
+        ("CellSize", layout.cell_size.to_string()),
+    ]
+    .into_iter()
+    .map(|(keyword, text)| (keyword.to_string(), text))
+    .collect()
+}
+
+/// Sets up a PNG encoder for 8-bit RGB, or RGBA if `transparent`, with the
+/// given text chunks
+pub fn png_encoder<W: Write>(
+    writer: W,
+    width: u32,
+    height: u32,
+    transparent: bool,
+    text: Vec<(String, String)>,
+) -> Result<png::Encoder<'static, W>, Box<dyn Error>> {
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(if transparent {
+        png::ColorType::Rgba
+    } else {
+        png::ColorType::Rgb
+    });
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, text) in text {
+        encoder.add_text_chunk(keyword, text)?;
+    }
+    Ok(encoder)
+}
+
+/// Encodes an image as a PNG with the given text chunks, dropping its alpha
+/// channel unless it is `transparent`
+pub fn write_png<W: Write>(
+    img: &RgbaImage,
+    transparent: bool,
+    writer: W,
+    text: Vec<(String, String)>,
+) -> Result<(), Box<dyn Error>> {
+    let encoder = png_encoder(writer, img.width(), img.height(), transparent, text)?;
+    let mut writer = encoder.write_header()?;
+    if transparent {
+        writer.write_image_data(img.as_raw())?;
+    } else {
+        let rgb: RgbImage = img.convert();
+        writer.write_image_data(rgb.as_raw())?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use elev::{Direction, Orientation};
+
+    use super::*;
+    use crate::extent::{Extent, Region};
+
+    /// Cells x -5 to 4 and z 10 to 12
+    fn layout(orientation: Orientation, cell_size: f64) -> ImageLayout {
+        let region = Region {
+            min_x: -5,
+            min_z: 10,
+            max_x: 4,
+            max_z: 12,
+        };
+        ImageLayout {
+            extent: Extent::of_region(&region, orientation).unwrap(),
+            cell_size,
+        }
+    }
+
+    #[test]
+    fn places_north_up_images() {
+        let georeference = Georeference::new(&layout(Orientation::default(), 1.0), 10, 3);
+        // Cell (4, 12) spans eastings -50 to -40 and northings 120 to 130
+        assert_eq!(
+            (georeference.origin_world_x, georeference.origin_world_z),
+            (4, 12)
+        );
+        assert_eq!(georeference.corner_easting_m, -50.0);
+        assert_eq!(georeference.corner_northing_m, 130.0);
+        assert_eq!(georeference.world_file(), "10\n0\n0\n-10\n-45\n125\n");
+    }
+
+    #[test]
+    fn places_rotated_scaled_images() {
+        let orientation = Orientation::new(Direction::East, false);
+        let georeference = Georeference::new(&layout(orientation, 4.0), 12, 40);
+        // Cell (-5, 12) is at the top left, with north to the left
+        assert_eq!(
+            (georeference.origin_world_x, georeference.origin_world_z),
+            (-5, 12)
+        );
+        assert_eq!(georeference.pixel_size_m, 2.5);
+        assert_eq!(georeference.corner_easting_m, 50.0);
+        assert_eq!(georeference.corner_northing_m, 130.0);
+        assert_eq!(
+            georeference.world_file(),
+            "0\n-2.5\n-2.5\n0\n48.75\n128.75\n"
+        );
+    }
+
+    #[test]
+    fn names_world_files_after_the_image_format() {
+        assert_eq!(world_file_path(Path::new("map.png")), Path::new("map.pgw"));
+        assert_eq!(
+            world_file_path(Path::new("a/map.tiff")),
+            Path::new("a/map.tfw")
+        );
+        assert_eq!(
+            sidecar_path(Path::new("map.r16")),
+            Path::new("map.r16.json")
+        );
+    }
+}
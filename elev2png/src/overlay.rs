@@ -0,0 +1,408 @@
+use elev::ElevEntry;
+use image::{Rgb, RgbaImage};
+
+use crate::extent::Extent;
+use crate::font::{self, GLYPH_HEIGHT};
+use crate::gradient::Gradient;
+
+/// Metres along each side of a cell
+pub const CELL_METRES: u32 = 10;
+
+const PAGE_BORDER_COLOR: Rgb<u8> = Rgb([255, 64, 64]);
+const GRID_COLOR: Rgb<u8> = Rgb([255, 255, 255]);
+const TEXT_COLOR: Rgb<u8> = Rgb([255, 255, 255]);
+const OUTLINE_COLOR: Rgb<u8> = Rgb([0, 0, 0]);
+
+/// Annotations drawn over a rendered map, whatever its colouring
+#[derive(Debug, Default, Clone)]
+pub struct Overlay<'a> {
+    pub page_borders: bool,
+    /// Spacing of the coordinate grid in cells
+    pub grid: Option<u32>,
+    /// Label grid lines, or page borders without a grid, with coordinates
+    pub labels: bool,
+    pub north_arrow: bool,
+    pub scale_bar: bool,
+    /// Text written in the top left corner, such as the date of a dump
+    pub caption: Option<String>,
+    /// Entries whose squares of cells are outlined, coloured by their radius
+    pub entry_outlines: &'a [ElevEntry],
+}
+
+/// Where the cells of the map are in the image
+#[derive(Debug, Clone, Copy)]
+pub struct ImageLayout {
+    pub extent: Extent,
+    /// Pixels along each side of a cell, which is fractional in scaled images
+    pub cell_size: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WorldAxis {
+    X,
+    Z,
+}
+
+impl ImageLayout {
+    /// Whether `axis` changes across the image, so that its lines run down
+    /// it, and by how much it changes per pixel along that image axis
+    fn axis_step(&self, axis: WorldAxis) -> (bool, i32) {
+        let orientation = self.extent.orientation;
+        let pick = |(x, z): (i32, i32)| if axis == WorldAxis::X { x } else { z };
+        match pick(orientation.right()) {
+            0 => (false, pick(orientation.down())),
+            step => (true, step),
+        }
+    }
+
+    fn origin(&self, axis: WorldAxis) -> i32 {
+        match axis {
+            WorldAxis::X => self.extent.origin_world_x,
+            WorldAxis::Z => self.extent.origin_world_z,
+        }
+    }
+
+    /// Position in pixels, along the image axis that `axis` changes along, of
+    /// the line at AW coordinate `coordinate`: the edge between the cells
+    /// `coordinate - 1` and `coordinate`
+    fn line_position(&self, axis: WorldAxis, coordinate: i32) -> i64 {
+        let (_, step) = self.axis_step(axis);
+        let origin = self.origin(axis);
+        let index = ((coordinate - origin) * step).max((coordinate - 1 - origin) * step);
+        (index as f64 * self.cell_size).round() as i64
+    }
+
+    /// The coordinates of every line of `axis` a multiple of `spacing` cells
+    /// apart that crosses an image of `width` by `height` pixels
+    fn lines(&self, axis: WorldAxis, spacing: u32, width: u32, height: u32) -> Vec<i32> {
+        let (across, step) = self.axis_step(axis);
+        let pixels = if across { width } else { height };
+        let cells = (pixels as f64 / self.cell_size).ceil() as i32;
+        let origin = self.origin(axis);
+        let far = origin + (cells - 1) * step;
+        let (first, last) = (origin.min(far), origin.max(far) + 1);
+
+        let spacing = spacing as i32;
+        let start = first.div_euclid(spacing) * spacing;
+        (start..=last)
+            .step_by(spacing as usize)
+            .filter(|&coordinate| coordinate >= first)
+            .collect()
+    }
+
+    /// Direction of north in the image as `(x, y)` pixel steps
+    fn north(&self) -> (i64, i64) {
+        let orientation = self.extent.orientation;
+        (
+            i64::from(orientation.right().1),
+            i64::from(orientation.down().1),
+        )
+    }
+}
+
+impl Overlay<'_> {
+    pub fn is_empty(&self) -> bool {
+        !self.page_borders
+            && self.grid.is_none()
+            && !self.labels
+            && !self.north_arrow
+            && !self.scale_bar
+            && self.caption.is_none()
+            && self.entry_outlines.is_empty()
+    }
+
+    pub fn draw(&self, img: &mut RgbaImage, layout: ImageLayout) {
+        let text_scale = (layout.cell_size / 4.0).clamp(1.0, 8.0) as u32;
+
+        if !self.entry_outlines.is_empty() {
+            draw_entry_outlines(img, layout, self.entry_outlines);
+        }
+        if let Some(spacing) = self.grid {
+            draw_lines(img, layout, spacing, GRID_COLOR, 0.5);
+        }
+        if self.page_borders {
+            draw_lines(img, layout, 128, PAGE_BORDER_COLOR, 1.0);
+        }
+        if self.labels {
+            draw_labels(img, layout, self.grid.unwrap_or(128), text_scale);
+        }
+        if self.north_arrow {
+            draw_north_arrow(img, layout, text_scale);
+        }
+        if self.scale_bar {
+            draw_scale_bar(img, layout, text_scale);
+        }
+        if let Some(caption) = &self.caption {
+            draw_caption(img, caption);
+        }
+    }
+}
+
+/// Writes `caption` in the top left corner, sized to the image rather than
+/// its cells so that it stays legible in shrunken images
+fn draw_caption(img: &mut RgbaImage, caption: &str) {
+    let scale = (img.width().min(img.height()) / 160).clamp(1, 8);
+    let margin = 4 * i64::from(scale);
+    font::draw_text(
+        img,
+        caption,
+        margin,
+        margin,
+        scale,
+        TEXT_COLOR,
+        OUTLINE_COLOR,
+    );
+}
+
+/// Outlines the cells each entry sets, inside their edges, from blue for the
+/// smallest radius through green and yellow to red for radii of 64 or more.
+/// Larger entries are drawn first so that the smaller ones inside them show.
+fn draw_entry_outlines(img: &mut RgbaImage, layout: ImageLayout, entries: &[ElevEntry]) {
+    let ramp = Gradient::new(vec![
+        (0.0, [60.0, 110.0, 255.0]),
+        (2.0, [60.0, 220.0, 90.0]),
+        (4.0, [250.0, 230.0, 50.0]),
+        (6.0, [240.0, 40.0, 30.0]),
+    ]);
+
+    let mut entries: Vec<&ElevEntry> = entries
+        .iter()
+        .filter(|entry| entry.node_radius > 0)
+        .collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.node_radius));
+
+    let (x_across, _) = layout.axis_step(WorldAxis::X);
+    for entry in entries {
+        let (xs, zs) = entry.world_cells();
+        if xs.is_empty() || zs.is_empty() {
+            continue;
+        }
+        // The first and last pixel inside the entry's cells along an axis
+        let span = |axis: WorldAxis, cells: std::ops::Range<i32>| {
+            let (a, b) = (
+                layout.line_position(axis, cells.start),
+                layout.line_position(axis, cells.end),
+            );
+            (a.min(b), (a.max(b) - 1).max(a.min(b)))
+        };
+        let (x_span, z_span) = (span(WorldAxis::X, xs), span(WorldAxis::Z, zs));
+        let ((left, right), (top, bottom)) = if x_across {
+            (x_span, z_span)
+        } else {
+            (z_span, x_span)
+        };
+
+        let color = ramp.color_at(f64::from(entry.node_radius).log2());
+        for x in left..=right {
+            blend_pixel(img, x, top, color, 1.0);
+            if bottom != top {
+                blend_pixel(img, x, bottom, color, 1.0);
+            }
+        }
+        for y in top + 1..bottom {
+            blend_pixel(img, left, y, color, 1.0);
+            if right != left {
+                blend_pixel(img, right, y, color, 1.0);
+            }
+        }
+    }
+}
+
+/// Lines every `spacing` cells across the whole image
+fn draw_lines(
+    img: &mut RgbaImage,
+    layout: ImageLayout,
+    spacing: u32,
+    color: Rgb<u8>,
+    opacity: f32,
+) {
+    let (width, height) = img.dimensions();
+    for axis in [WorldAxis::X, WorldAxis::Z] {
+        let (across, _) = layout.axis_step(axis);
+        for coordinate in layout.lines(axis, spacing, width, height) {
+            let position = layout.line_position(axis, coordinate);
+            if across {
+                for y in 0..height {
+                    blend_pixel(img, position, i64::from(y), color, opacity);
+                }
+            } else {
+                for x in 0..width {
+                    blend_pixel(img, i64::from(x), position, color, opacity);
+                }
+            }
+        }
+    }
+}
+
+/// AW coordinates count cells, with north and west positive
+pub fn aw_coordinates(world_x: i32, world_z: i32) -> String {
+    let north_south = match world_z {
+        z if z >= 0 => format!("{z}N"),
+        z => format!("{}S", -z),
+    };
+    let west_east = match world_x {
+        x if x >= 0 => format!("{x}W"),
+        x => format!("{}E", -x),
+    };
+    format!("{north_south} {west_east}")
+}
+
+/// Labels grid intersections with their coordinates, skipping lines where
+/// the labels would not fit between them
+fn draw_labels(img: &mut RgbaImage, layout: ImageLayout, spacing: u32, text_scale: u32) {
+    let (width, height) = img.dimensions();
+    let xs = layout.lines(WorldAxis::X, spacing, width, height);
+    let zs = layout.lines(WorldAxis::Z, spacing, width, height);
+    let widest = xs
+        .iter()
+        .flat_map(|&x| zs.iter().map(move |&z| aw_coordinates(x, z)))
+        .map(|label| font::text_width(&label, text_scale))
+        .max()
+        .unwrap_or(0);
+
+    // Skip lines so that labels fit between them across and down the image
+    let spacing_pixels = ((spacing as f64 * layout.cell_size) as u32).max(1);
+    let step_across = (widest + 6).div_ceil(spacing_pixels).max(1) as usize;
+    let step_down = (GLYPH_HEIGHT * text_scale + 6)
+        .div_ceil(spacing_pixels)
+        .max(1) as usize;
+    let (x_across, _) = layout.axis_step(WorldAxis::X);
+    let (step_x, step_z) = if x_across {
+        (step_across, step_down)
+    } else {
+        (step_down, step_across)
+    };
+
+    // Each label sits above and to the left of its intersection
+    for &world_x in xs.iter().step_by(step_x) {
+        for &world_z in zs.iter().step_by(step_z) {
+            let position_x = layout.line_position(WorldAxis::X, world_x);
+            let position_z = layout.line_position(WorldAxis::Z, world_z);
+            let (x, y) = if x_across {
+                (position_x, position_z)
+            } else {
+                (position_z, position_x)
+            };
+
+            let label = aw_coordinates(world_x, world_z);
+            let label_width = font::text_width(&label, text_scale);
+            font::draw_text(
+                img,
+                &label,
+                x - i64::from(label_width) - 3,
+                y - i64::from(GLYPH_HEIGHT * text_scale) - 3,
+                text_scale,
+                TEXT_COLOR,
+                OUTLINE_COLOR,
+            );
+        }
+    }
+}
+
+/// An arrow pointing north with an "N" behind it, in the top right corner
+fn draw_north_arrow(img: &mut RgbaImage, layout: ImageLayout, text_scale: u32) {
+    let size = 8 * i64::from(text_scale);
+    let margin = 4 * i64::from(text_scale);
+    let label_room = i64::from(GLYPH_HEIGHT * text_scale) + 5 * i64::from(text_scale);
+    let center_x = img.width() as i64 - margin - size - label_room;
+    let center_y = margin + size + label_room;
+
+    // Walk from the tip back along the arrow, widening as it goes
+    let (north_x, north_y) = layout.north();
+    let (side_x, side_y) = (-north_y, north_x);
+    let (tip_x, tip_y) = (center_x + north_x * size, center_y + north_y * size);
+    for along in 0..=size * 2 {
+        let half_width = along / 2;
+        for across in -half_width - 1..=half_width + 1 {
+            let edge = across.abs() > half_width || along == size * 2;
+            let color = if edge { OUTLINE_COLOR } else { TEXT_COLOR };
+            // One half is darker so the arrow reads as a compass needle
+            let color = if !edge && across > 0 {
+                Rgb([160, 160, 160])
+            } else {
+                color
+            };
+            let x = tip_x - north_x * along + side_x * across;
+            let y = tip_y - north_y * along + side_y * across;
+            blend_pixel(img, x, y, color, 1.0);
+        }
+    }
+
+    let label_width = i64::from(font::text_width("N", text_scale));
+    let label_height = i64::from(GLYPH_HEIGHT * text_scale);
+    let behind = size + 5 * i64::from(text_scale) + label_height / 2;
+    font::draw_text(
+        img,
+        "N",
+        center_x - north_x * behind - label_width / 2,
+        center_y - north_y * behind - label_height / 2,
+        text_scale,
+        TEXT_COLOR,
+        OUTLINE_COLOR,
+    );
+}
+
+/// A bar of a round number of metres, about a fifth of the image wide, in
+/// the bottom left corner
+fn draw_scale_bar(img: &mut RgbaImage, layout: ImageLayout, text_scale: u32) {
+    let metres_per_pixel = CELL_METRES as f64 / layout.cell_size;
+    let target = img.width() as f64 / 5.0 * metres_per_pixel;
+    let metres = nice_length(target.max(CELL_METRES as f64));
+    let length = (metres / metres_per_pixel).round() as i64;
+
+    let margin = 6 * i64::from(text_scale);
+    let thickness = 2 * i64::from(text_scale);
+    let left = margin;
+    let bottom = img.height() as i64 - margin;
+
+    for y in bottom - thickness - 1..=bottom {
+        for x in left - 1..=left + length {
+            let inside = y > bottom - thickness - 1 && y < bottom && x >= left && x < left + length;
+            let color = if inside { TEXT_COLOR } else { OUTLINE_COLOR };
+            blend_pixel(img, x, y, color, 1.0);
+        }
+    }
+
+    let label = if metres >= 1000.0 {
+        format!("{} km", metres / 1000.0)
+    } else {
+        format!("{metres} m")
+    };
+    font::draw_text(
+        img,
+        &label,
+        left,
+        bottom - thickness - 4 - i64::from(GLYPH_HEIGHT * text_scale),
+        text_scale,
+        TEXT_COLOR,
+        OUTLINE_COLOR,
+    );
+}
+
+/// The largest 1, 2 or 5 times a power of ten no longer than `target`
+fn nice_length(target: f64) -> f64 {
+    let magnitude = 10f64.powf(target.log10().floor());
+    [5.0, 2.0, 1.0]
+        .into_iter()
+        .map(|step| step * magnitude)
+        .find(|&length| length <= target)
+        .unwrap_or(magnitude)
+}
+
+/// Draws `color` with `opacity` over a pixel, which may itself be partly
+/// transparent
+fn blend_pixel(img: &mut RgbaImage, x: i64, y: i64, color: Rgb<u8>, opacity: f32) {
+    if x < 0 || y < 0 || x >= img.width() as i64 || y >= img.height() as i64 {
+        return;
+    }
+    let pixel = img.get_pixel_mut(x as u32, y as u32);
+    let below = pixel[3] as f32 / 255.0 * (1.0 - opacity);
+    let alpha = opacity + below;
+    if alpha <= 0.0 {
+        return;
+    }
+    for (channel, &value) in pixel.0.iter_mut().zip(color.0.iter()) {
+        *channel = ((*channel as f32 * below + value as f32 * opacity) / alpha).round() as u8;
+    }
+    pixel[3] = (alpha * 255.0).round() as u8;
+}
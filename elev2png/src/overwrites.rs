@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+use elev::ElevDump;
+
+/// How many entries of a dump set each cell, counting every entry that
+/// overwrote a cell before the last
+pub struct OverwriteCounts {
+    pages: HashMap<(i32, i32), Vec<u16>>,
+}
+
+impl OverwriteCounts {
+    pub fn of_dump(dump: &ElevDump) -> Self {
+        let mut pages: HashMap<(i32, i32), Vec<u16>> = HashMap::new();
+        for entry in &dump.entries {
+            let counts = pages
+                .entry((entry.page_x, entry.page_z))
+                .or_insert_with(|| vec![0; 128 * 128]);
+            let (xs, zs) = entry.world_cells();
+            for world_z in zs {
+                for world_x in xs.clone() {
+                    let index =
+                        world_z.rem_euclid(128) as usize * 128 + world_x.rem_euclid(128) as usize;
+                    counts[index] = counts[index].saturating_add(1);
+                }
+            }
+        }
+        OverwriteCounts { pages }
+    }
+
+    /// The number of entries that set the cell at world cell coordinates
+    /// (`world_x`, `world_z`)
+    pub fn get(&self, world_x: i32, world_z: i32) -> u16 {
+        self.pages
+            .get(&(world_x.div_euclid(128), world_z.div_euclid(128)))
+            .map_or(0, |counts| {
+                counts[world_z.rem_euclid(128) as usize * 128 + world_x.rem_euclid(128) as usize]
+            })
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use elev::Rotation;
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+
+use crate::texture_catalog::{ColorSummary, TextureCatalog};
+
+/// Colours for texture ids, read from a TOML or JSON file, e.g.
+///
+/// ```toml
+/// [textures]
+/// 0 = "#3a5f2c"
+/// 1 = [120, 110, 90]
+/// 2 = { color = "#808080", r2 = "#707070" }
+/// ```
+///
+/// A colour given for a specific rotation (`r0` to `r3`) takes precedence
+/// over the texture's `color`.
+#[derive(Debug, Default)]
+pub struct Palette {
+    textures: BTreeMap<u32, PaletteEntry>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct PaletteEntry {
+    color: Option<Rgb<u8>>,
+    rotations: [Option<Rgb<u8>>; 4],
+}
+
+/// Entries are read as plain values first, so that what is wrong with one
+/// can be reported along with its texture id
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PaletteFile<T> {
+    textures: BTreeMap<String, T>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum EntryRepr {
+    Color(ColorRepr),
+    Rotations(RotationsRepr),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RotationsRepr {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    color: Option<ColorRepr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    r0: Option<ColorRepr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    r1: Option<ColorRepr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    r2: Option<ColorRepr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    r3: Option<ColorRepr>,
+}
+
+impl EntryRepr {
+    /// Picks the kind of entry by the shape of the value, as an untagged enum
+    /// would only report that the value matches neither
+    fn from_value(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        if value.is_object() {
+            serde_json::from_value(value).map(EntryRepr::Rotations)
+        } else {
+            serde_json::from_value(value).map(EntryRepr::Color)
+        }
+    }
+}
+
+/// Either `[r, g, b]` or `"#rrggbb"`
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ColorRepr {
+    Rgb([u8; 3]),
+    Hex(String),
+}
+
+impl ColorRepr {
+    fn to_rgb(&self) -> Result<Rgb<u8>, String> {
+        match self {
+            ColorRepr::Rgb(rgb) => Ok(Rgb(*rgb)),
+            ColorRepr::Hex(hex) => {
+                let digits = hex.strip_prefix('#').unwrap_or(hex);
+                let value = u32::from_str_radix(digits, 16)
+                    .ok()
+                    .filter(|_| digits.len() == 6)
+                    .ok_or_else(|| format!("Invalid colour: {hex:?}"))?;
+                Ok(Rgb([(value >> 16) as u8, (value >> 8) as u8, value as u8]))
+            }
+        }
+    }
+
+    fn from_rgb(color: Rgb<u8>) -> Self {
+        let [r, g, b] = color.0;
+        ColorRepr::Hex(format!("#{r:02x}{g:02x}{b:02x}"))
+    }
+}
+
+enum PaletteFormat {
+    Toml,
+    Json,
+}
+
+impl PaletteFormat {
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => PaletteFormat::Json,
+            _ => PaletteFormat::Toml,
+        }
+    }
+}
+
+impl Palette {
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        let file: PaletteFile<serde_json::Value> = match PaletteFormat::from_path(path) {
+            PaletteFormat::Toml => toml::from_str(&contents)?,
+            PaletteFormat::Json => serde_json::from_str(&contents)?,
+        };
+
+        let mut textures = BTreeMap::new();
+        for (key, value) in file.textures {
+            let texture_id: u32 = key
+                .parse()
+                .map_err(|e| format!("Invalid texture id {key:?}: {e}"))?;
+            let repr = EntryRepr::from_value(value)
+                .map_err(|e| format!("Invalid entry for texture {texture_id}: {e}"))?;
+
+            let convert =
+                |color: &Option<ColorRepr>| color.as_ref().map(ColorRepr::to_rgb).transpose();
+            let entry = match &repr {
+                EntryRepr::Color(color) => PaletteEntry {
+                    color: Some(color.to_rgb()?),
+                    rotations: [None; 4],
+                },
+                EntryRepr::Rotations(RotationsRepr {
+                    color,
+                    r0,
+                    r1,
+                    r2,
+                    r3,
+                }) => PaletteEntry {
+                    color: convert(color)?,
+                    rotations: [convert(r0)?, convert(r1)?, convert(r2)?, convert(r3)?],
+                },
+            };
+            textures.insert(texture_id, entry);
+        }
+
+        Ok(Palette { textures })
+    }
+
+    pub fn to_file(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let textures = self
+            .textures
+            .iter()
+            .map(|(texture_id, entry)| {
+                let repr = match (entry.color, entry.rotations) {
+                    (Some(color), [None, None, None, None]) => {
+                        EntryRepr::Color(ColorRepr::from_rgb(color))
+                    }
+                    (color, [r0, r1, r2, r3]) => EntryRepr::Rotations(RotationsRepr {
+                        color: color.map(ColorRepr::from_rgb),
+                        r0: r0.map(ColorRepr::from_rgb),
+                        r1: r1.map(ColorRepr::from_rgb),
+                        r2: r2.map(ColorRepr::from_rgb),
+                        r3: r3.map(ColorRepr::from_rgb),
+                    }),
+                };
+                (texture_id.to_string(), repr)
+            })
+            .collect();
+        let file = PaletteFile { textures };
+
+        let contents = match PaletteFormat::from_path(path) {
+            PaletteFormat::Toml => toml::to_string(&file)?,
+            PaletteFormat::Json => serde_json::to_string_pretty(&file)?,
+        };
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// A palette holding the chosen summary colour of every catalogued texture
+    pub fn from_catalog(catalog: &TextureCatalog, summary: ColorSummary) -> Self {
+        let textures = catalog
+            .iter()
+            .map(|(texture_id, colors)| {
+                let entry = PaletteEntry {
+                    color: Some(colors.get(summary)),
+                    rotations: [None; 4],
+                };
+                (texture_id, entry)
+            })
+            .collect();
+        Palette { textures }
+    }
+
+    pub fn color(&self, texture_id: u32, rotation: Rotation) -> Option<Rgb<u8>> {
+        let entry = self.textures.get(&texture_id)?;
+        entry.rotations[usize::from(rotation.index())].or(entry.color)
+    }
+
+    /// Texture ids whose entry gives a `color`, and so colours every rotation
+    /// of the texture without it being loaded
+    pub fn colored_texture_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.textures
+            .iter()
+            .filter(|(_, entry)| entry.color.is_some())
+            .map(|(&texture_id, _)| texture_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory for the files of one test, removed with everything in it
+    /// when dropped, even if the test fails
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new(test: &str) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("elev2png-palette-{test}-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn file(&self, name: &str, contents: &str) -> std::path::PathBuf {
+            let path = self.0.join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const TOML: &str = r##"
+[textures]
+0 = "#3a5f2c"
+1 = [120, 110, 90]
+2 = { color = "#808080", r2 = "#707070" }
+3 = { r1 = [1, 2, 3] }
+"##;
+
+    #[test]
+    fn loads_colours_and_rotations() {
+        let dir = TempDir::new("load");
+        let palette = Palette::from_file(&dir.file("load.toml", TOML)).unwrap();
+
+        assert_eq!(
+            palette.color(0, Rotation::R3),
+            Some(Rgb([0x3a, 0x5f, 0x2c]))
+        );
+        assert_eq!(palette.color(1, Rotation::R0), Some(Rgb([120, 110, 90])));
+        assert_eq!(palette.color(2, Rotation::R1), Some(Rgb([0x80; 3])));
+        assert_eq!(palette.color(2, Rotation::R2), Some(Rgb([0x70; 3])));
+        assert_eq!(palette.color(3, Rotation::R1), Some(Rgb([1, 2, 3])));
+        assert_eq!(palette.color(3, Rotation::R0), None);
+        assert_eq!(palette.color(4, Rotation::R0), None);
+
+        // Texture 3 still needs its texture for the other rotations
+        let colored: Vec<u32> = palette.colored_texture_ids().collect();
+        assert_eq!(colored, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn saves_and_reloads_in_both_formats() {
+        let dir = TempDir::new("save");
+        let palette = Palette::from_file(&dir.file("source.toml", TOML)).unwrap();
+
+        for name in ["saved.toml", "saved.json"] {
+            let path = dir.0.join(name);
+            palette.to_file(&path).unwrap();
+            let reloaded = Palette::from_file(&path).unwrap();
+            for texture_id in 0..5 {
+                for rotation in (0..4).filter_map(Rotation::from_index) {
+                    assert_eq!(
+                        reloaded.color(texture_id, rotation),
+                        palette.color(texture_id, rotation),
+                        "{name}: texture {texture_id} {rotation:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reports_invalid_entries() {
+        let dir = TempDir::new("invalid");
+        let error = |name, contents| {
+            Palette::from_file(&dir.file(name, contents))
+                .unwrap_err()
+                .to_string()
+        };
+
+        let typo = error("typo.toml", "[textures]\n5 = { colour = \"#ffffff\" }\n");
+        assert!(
+            typo.contains("texture 5") && typo.contains("colour"),
+            "{typo}"
+        );
+        let typo = error("typo.json", r#"{"textures": {"6": {"r4": [0, 0, 0]}}}"#);
+        assert!(typo.contains("texture 6") && typo.contains("r4"), "{typo}");
+        assert!(error("hex.toml", "[textures]\n1 = \"#12345\"\n").contains("Invalid colour"));
+        assert!(error("id.toml", "[textures]\nx = \"#123456\"\n").contains("Invalid texture id"));
+        assert!(error("table.toml", "[texture]\n1 = \"#123456\"\n").contains("texture"));
+    }
+}
@@ -0,0 +1,275 @@
+use std::ops::Range;
+
+use elev::{ElevCell, ElevMap, Orientation};
+use image::{Rgb, RgbaImage};
+use rayon::prelude::*;
+
+use crate::color_mode::ColorMode;
+use crate::extent::Extent;
+use crate::hillshade::{self, Hillshade};
+use crate::texture_patches;
+
+/// Produces the pixels of every cell of a map
+pub struct Renderer<'a> {
+    pub map: &'a ElevMap,
+    pub mode: &'a ColorMode,
+    pub hillshade: Option<Hillshade>,
+    /// Draw only the hillshade, in greyscale
+    pub hillshade_only: bool,
+    /// Width and height in pixels of the patch drawn for each cell
+    pub cell_size: u32,
+}
+
+/// Most rows of cells drawn by one task, so that even thin bands are spread
+/// over several threads
+const TASK_ROWS: u32 = 4;
+
+impl Renderer<'_> {
+    /// Draws the cells of `extent` into an image, `cell_size` pixels to a
+    /// cell. Cells set by an entry are opaque and the rest of the image is
+    /// transparent.
+    pub fn render(&self, extent: Extent) -> RgbaImage {
+        self.render_rows(extent, 0..extent.height)
+    }
+
+    /// Draws the band of `extent` made of the rows of cells `rows`. Runs of
+    /// rows on the same pages are drawn on their own threads into their own
+    /// part of the band, looking up each page once rather than every cell.
+    pub fn render_rows(&self, extent: Extent, rows: Range<u32>) -> RgbaImage {
+        let cell_size = self.cell_size as usize;
+        let width = extent.width as usize * cell_size;
+        let height = rows.len() * cell_size;
+        let row_len = width * 4;
+        let mut buffer = vec![0; row_len * height];
+
+        let rows = page_runs(rows, |pixel_y| extent.world_cell(0, pixel_y));
+        let columns = page_runs(0..extent.width, |pixel_x| extent.world_cell(pixel_x, 0));
+
+        let mut tasks = Vec::new();
+        let mut rest = buffer.as_mut_slice();
+        for run in rows {
+            for start in run.clone().step_by(TASK_ROWS as usize) {
+                let rows = start..(start + TASK_ROWS).min(run.end);
+                let (part, tail) = rest.split_at_mut(rows.len() * cell_size * row_len);
+                tasks.push((rows, part));
+                rest = tail;
+            }
+        }
+
+        tasks.into_par_iter().for_each(|(rows, part)| {
+            for columns in &columns {
+                let (world_x, world_z) = extent.world_cell(columns.start, rows.start);
+                let Some(page) = self
+                    .map
+                    .get_page(world_x.div_euclid(128), world_z.div_euclid(128))
+                else {
+                    continue;
+                };
+
+                for pixel_y in rows.clone() {
+                    let part_y = (pixel_y - rows.start) as usize * cell_size;
+                    for pixel_x in columns.clone() {
+                        let (world_x, world_z) = extent.world_cell(pixel_x, pixel_y);
+                        let (x, z) = (world_x.rem_euclid(128) as u8, world_z.rem_euclid(128) as u8);
+                        let Some(cell) = page.get_cell(x, z) else {
+                            continue;
+                        };
+                        let alpha = if page.is_defined(x, z) { 255 } else { 0 };
+                        self.render_cell(
+                            extent.orientation,
+                            (world_x, world_z),
+                            cell,
+                            |patch_x, patch_z, color| {
+                                let [r, g, b] = color.0;
+                                let offset = (part_y + patch_z as usize) * row_len
+                                    + (pixel_x as usize * cell_size + patch_x as usize) * 4;
+                                part[offset..offset + 4].copy_from_slice(&[r, g, b, alpha]);
+                            },
+                        );
+                    }
+                }
+            }
+        });
+
+        RgbaImage::from_raw(width as u32, height as u32, buffer)
+            .expect("the buffer holds every pixel of the image")
+    }
+
+    /// Calls `put` with the colour of each pixel of the patch drawn for the
+    /// cell at (`world_x`, `world_z`). Patch coordinates follow the image,
+    /// laid out by `orientation`, and textures are turned to match.
+    pub fn render_cell(
+        &self,
+        orientation: Orientation,
+        (world_x, world_z): (i32, i32),
+        cell: &ElevCell,
+        mut put: impl FnMut(u32, u32, Rgb<u8>),
+    ) {
+        let shade = self
+            .hillshade
+            .as_ref()
+            .map(|hillshade| hillshade.shade(self.map, world_x, world_z));
+
+        let fill = |put: &mut dyn FnMut(u32, u32, Rgb<u8>), color: Rgb<u8>| {
+            for patch_z in 0..self.cell_size {
+                for patch_x in 0..self.cell_size {
+                    put(patch_x, patch_z, color);
+                }
+            }
+        };
+
+        if self.hillshade_only {
+            fill(&mut put, hillshade::grey(shade.unwrap_or(1.0)));
+            return;
+        }
+
+        let ColorMode::Texture(texture) = self.mode else {
+            let mut color = self.mode.cell_color(cell, (world_x, world_z), || {
+                hillshade::slope_degrees(self.map, world_x, world_z)
+            });
+            if let Some(shade) = shade {
+                color = hillshade::multiply(color, shade);
+            }
+            fill(&mut put, color);
+            return;
+        };
+
+        // Water is drawn flat
+        let shade = shade.filter(|_| !texture.depth.is_underwater(cell.height));
+        let base_color = texture.base_color(cell);
+
+        for patch_z in 0..self.cell_size {
+            for patch_x in 0..self.cell_size {
+                let (texel_x, texel_z) = texture_patches::patch_coordinates(
+                    orientation,
+                    patch_x,
+                    patch_z,
+                    self.cell_size,
+                );
+                let texel = texture.texel(cell, texel_x, texel_z);
+                let mut color = texture
+                    .depth
+                    .apply(texel.unwrap_or(base_color), cell.height);
+                if let Some(shade) = shade {
+                    color = hillshade::multiply(color, shade);
+                }
+                put(patch_x, patch_z, color);
+            }
+        }
+    }
+}
+
+/// Splits `pixels` along a row or column of the image into runs that each lie
+/// on one page, given the world cell at each pixel
+fn page_runs(pixels: Range<u32>, world_cell: impl Fn(u32) -> (i32, i32)) -> Vec<Range<u32>> {
+    let page = |pixel| {
+        let (world_x, world_z) = world_cell(pixel);
+        (world_x.div_euclid(128), world_z.div_euclid(128))
+    };
+
+    let mut runs = Vec::new();
+    let mut start = pixels.start;
+    for pixel in pixels.clone().skip(1) {
+        if page(pixel) != page(start) {
+            runs.push(start..pixel);
+            start = pixel;
+        }
+    }
+    if !pixels.is_empty() {
+        runs.push(start..pixels.end);
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use elev::{Direction, Rotation, TextureResolver};
+    use image::RgbImage;
+
+    use super::*;
+    use crate::color_mode::{DepthShading, TextureColoring};
+    use crate::extent::Region;
+    use crate::palette::Palette;
+    use crate::texture_catalog::{ColorSummary, TextureCatalog};
+    use crate::texture_patches::TexturePatches;
+
+    #[test]
+    fn turns_texture_patches_with_the_image() {
+        const NW: Rgb<u8> = Rgb([200, 0, 0]);
+        const NE: Rgb<u8> = Rgb([0, 200, 0]);
+        const SW: Rgb<u8> = Rgb([0, 0, 200]);
+        const SE: Rgb<u8> = Rgb([200, 200, 0]);
+
+        // Texture pixels run east then south from the north-west corner
+        let mut texture = RgbImage::new(2, 2);
+        texture.put_pixel(0, 0, NW);
+        texture.put_pixel(1, 0, NE);
+        texture.put_pixel(0, 1, SW);
+        texture.put_pixel(1, 1, SE);
+        let resolver = TextureResolver::new(std::env::temp_dir());
+        let mode = ColorMode::Texture(TextureColoring {
+            palette: Palette::default(),
+            catalog: TextureCatalog::load(&resolver, [], None, &BTreeSet::new()),
+            patches: Some(TexturePatches::new(HashMap::from([(5, texture)]), 2)),
+            summary: ColorSummary::Average,
+            depth: DepthShading {
+                water_level: 0,
+                max_height: 1000.0,
+                contrast_center: 500.0,
+                contrast_width: 1000.0,
+            },
+        });
+        let ColorMode::Texture(coloring) = &mode else {
+            unreachable!()
+        };
+
+        let mut map = ElevMap::new();
+        let cell = ElevCell {
+            texture_id: 5,
+            rotation: Rotation::R0,
+            height: 800,
+        };
+        map.set_world_cell(3, 7, cell);
+        let renderer = Renderer {
+            map: &map,
+            mode: &mode,
+            hillshade: None,
+            hillshade_only: false,
+            cell_size: 2,
+        };
+        let region = Region {
+            min_x: 3,
+            min_z: 7,
+            max_x: 3,
+            max_z: 7,
+        };
+
+        // Top left, top right, bottom left and bottom right
+        for (up, mirrored, corners) in [
+            (Direction::North, false, [NW, NE, SW, SE]),
+            (Direction::North, true, [NE, NW, SE, SW]),
+            (Direction::East, false, [NE, SE, NW, SW]),
+            (Direction::East, true, [SE, NE, SW, NW]),
+            (Direction::South, false, [SE, SW, NE, NW]),
+            (Direction::South, true, [SW, SE, NW, NE]),
+            (Direction::West, false, [SW, NW, SE, NE]),
+            (Direction::West, true, [NW, SW, NE, SE]),
+        ] {
+            let orientation = Orientation::new(up, mirrored);
+            let extent = Extent::of_region(&region, orientation).unwrap();
+            let img = renderer.render(extent);
+            let at = |x, y| {
+                let [r, g, b, _] = img.get_pixel(x, y).0;
+                Rgb([r, g, b])
+            };
+            let expected = corners.map(|color| coloring.depth.apply(color, cell.height));
+            assert_eq!(
+                [at(0, 0), at(1, 0), at(0, 1), at(1, 1)],
+                expected,
+                "{orientation}"
+            );
+        }
+    }
+}
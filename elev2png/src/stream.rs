@@ -0,0 +1,166 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
+use std::path::Path;
+
+use image::buffer::ConvertBuffer;
+use image::RgbImage;
+use tiff::encoder::colortype::{self, ColorType};
+use tiff::encoder::{TiffEncoder, TiffKind};
+use tiff::tags::Tag;
+
+use crate::extent::Extent;
+use crate::metadata;
+use crate::overlay::ImageLayout;
+use crate::render::Renderer;
+
+/// Rows of pixels drawn and encoded at a time, at least one row of cells
+const BAND_PIXEL_ROWS: u32 = 512;
+
+/// Most image data written to a classic TIFF, whose 32-bit offsets can't
+/// reach past 4 GiB. Larger images are written as BigTIFFs.
+const CLASSIC_TIFF_MAX_BYTES: u64 = 0xF000_0000;
+
+/// Draws a map a band of rows at a time, encoding each band before drawing
+/// the next, so that memory use grows with the width of the image but not
+/// its height. Writes a PNG to stdout for "-", or a PNG or TIFF file.
+pub fn write_streamed(
+    renderer: &Renderer,
+    extent: Extent,
+    transparent: bool,
+    output: &Path,
+) -> Result<(), Box<dyn Error>> {
+    if output == Path::new("-") {
+        return write_png(renderer, extent, transparent, std::io::stdout().lock());
+    }
+
+    let extension = output
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("png") => write_png(
+            renderer,
+            extent,
+            transparent,
+            BufWriter::new(File::create(output)?),
+        ),
+        Some("tif" | "tiff") => write_tiff(renderer, extent, transparent, output),
+        _ => Err("Only PNG and TIFF images can be streamed".into()),
+    }
+}
+
+/// Rows of cells in each band
+fn band_rows(renderer: &Renderer) -> u32 {
+    (BAND_PIXEL_ROWS / renderer.cell_size).max(1)
+}
+
+/// The samples of each band in turn, RGBA if `transparent` or else RGB
+fn bands<'a>(
+    renderer: &'a Renderer,
+    extent: Extent,
+    transparent: bool,
+) -> impl Iterator<Item = Vec<u8>> + 'a {
+    let band_rows = band_rows(renderer);
+    (0..extent.height)
+        .step_by(band_rows as usize)
+        .map(move |start| {
+            let band = renderer.render_rows(extent, start..(start + band_rows).min(extent.height));
+            if transparent {
+                band.into_raw()
+            } else {
+                ConvertBuffer::<RgbImage>::convert(&band).into_raw()
+            }
+        })
+}
+
+fn write_png<W: Write>(
+    renderer: &Renderer,
+    extent: Extent,
+    transparent: bool,
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = extent.scaled_size(renderer.cell_size)?;
+    let layout = ImageLayout {
+        extent,
+        cell_size: renderer.cell_size as f64,
+    };
+    let encoder = metadata::png_encoder(
+        writer,
+        width,
+        height,
+        transparent,
+        metadata::text_chunks(&layout),
+    )?;
+
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer()?;
+    for band in bands(renderer, extent, transparent) {
+        stream.write_all(&band)?;
+    }
+    stream.finish()?;
+    writer.finish()?;
+    Ok(())
+}
+
+fn write_tiff(
+    renderer: &Renderer,
+    extent: Extent,
+    transparent: bool,
+    output: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = extent.scaled_size(renderer.cell_size)?;
+    let channels = if transparent { 4 } else { 3 };
+    let big = width as u64 * height as u64 * channels > CLASSIC_TIFF_MAX_BYTES;
+    let file = BufWriter::new(File::create(output)?);
+
+    match (big, transparent) {
+        (false, false) => write_tiff_strips::<colortype::RGB8, _, _>(
+            TiffEncoder::new(file)?,
+            renderer,
+            extent,
+            transparent,
+        ),
+        (false, true) => write_tiff_strips::<colortype::RGBA8, _, _>(
+            TiffEncoder::new(file)?,
+            renderer,
+            extent,
+            transparent,
+        ),
+        (true, false) => write_tiff_strips::<colortype::RGB8, _, _>(
+            TiffEncoder::new_big(file)?,
+            renderer,
+            extent,
+            transparent,
+        ),
+        (true, true) => write_tiff_strips::<colortype::RGBA8, _, _>(
+            TiffEncoder::new_big(file)?,
+            renderer,
+            extent,
+            transparent,
+        ),
+    }
+}
+
+/// Writes each band as a strip of a TIFF in the colour type `C`, which must
+/// be RGBA8 for transparent images and RGB8 otherwise
+fn write_tiff_strips<C: ColorType<Inner = u8>, W: Write + Seek, K: TiffKind>(
+    mut encoder: TiffEncoder<W, K>,
+    renderer: &Renderer,
+    extent: Extent,
+    transparent: bool,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = extent.scaled_size(renderer.cell_size)?;
+
+    let mut image = encoder.new_image::<C>(width, height)?;
+    image.rows_per_strip(band_rows(renderer) * renderer.cell_size)?;
+    if transparent {
+        // Marks the fourth sample as unassociated alpha
+        image.encoder().write_tag(Tag::ExtraSamples, 2u16)?;
+    }
+    for band in bands(renderer, extent, transparent) {
+        image.write_strip(&band)?;
+    }
+    image.finish()?;
+    Ok(())
+}
@@ -0,0 +1,448 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use clap::ValueEnum;
+use elev::{TextureLocation, TextureResolver};
+use image::{Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+
+/// Name of the file in the user's cache directory that caches the summary
+/// colours of every texture decoded so far, wherever it was found
+const CACHE_FILE_NAME: &str = "texture-colors.json";
+
+/// Which summary colour represents a texture on the map
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorSummary {
+    /// Mean of the sRGB values
+    #[default]
+    Average,
+    /// Mean computed in linear light
+    Linear,
+    /// Per-channel median
+    Median,
+    /// Mean of the most common colour bucket
+    Dominant,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TextureColors {
+    pub average: [u8; 3],
+    pub linear: [u8; 3],
+    pub median: [u8; 3],
+    pub dominant: [u8; 3],
+}
+
+impl TextureColors {
+    pub fn from_image(img: &RgbImage) -> Self {
+        TextureColors {
+            average: average_color(img),
+            linear: linear_average_color(img),
+            median: median_color(img),
+            dominant: dominant_color(img),
+        }
+    }
+
+    pub fn get(&self, summary: ColorSummary) -> Rgb<u8> {
+        Rgb(match summary {
+            ColorSummary::Average => self.average,
+            ColorSummary::Linear => self.linear,
+            ColorSummary::Median => self.median,
+            ColorSummary::Dominant => self.dominant,
+        })
+    }
+}
+
+/// Summary colours of the textures used by a map, computed once per texture
+/// file and cached between runs.
+pub struct TextureCatalog {
+    colors: HashMap<u32, TextureColors>,
+    /// Decoded textures kept for drawing them whole
+    images: HashMap<u32, RgbImage>,
+}
+
+/// What the cache knows of a texture, and its image if it was decoded
+struct Lookup {
+    colors: TextureColors,
+    changed: bool,
+    image: Option<RgbImage>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct ColorCache {
+    textures: BTreeMap<String, CacheEntry>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    mtime: u128,
+    size: u64,
+    hash: u64,
+    colors: TextureColors,
+}
+
+impl TextureCatalog {
+    /// Loads the colours of the textures, reading and updating the colour
+    /// cache at `cache_path` if one is given. The textures among `image_ids`
+    /// are decoded even when their colours are cached, and kept for
+    /// [`TextureCatalog::take_images`].
+    pub fn load(
+        resolver: &TextureResolver,
+        texture_ids: impl IntoIterator<Item = u32>,
+        cache_path: Option<&Path>,
+        image_ids: &BTreeSet<u32>,
+    ) -> Self {
+        let resolved = resolver.resolve_all(texture_ids);
+        if !resolved.missing.is_empty() {
+            eprintln!(
+                "No texture found in {:?} for ids: {:?}",
+                resolver.source(),
+                resolved.missing
+            );
+        }
+
+        let mut cache = cache_path.map(ColorCache::read).unwrap_or_default();
+        let mut cache_changed = false;
+
+        let mut colors = HashMap::new();
+        let mut images = HashMap::new();
+        for (texture_id, location) in resolved.found {
+            let keep_image = image_ids.contains(&texture_id);
+            match cache.lookup_or_insert(resolver, &location, keep_image) {
+                Ok(lookup) => {
+                    colors.insert(texture_id, lookup.colors);
+                    cache_changed |= lookup.changed;
+                    if let Some(image) = lookup.image {
+                        images.insert(texture_id, image);
+                    }
+                }
+                Err(why) => eprintln!("Failed to load texture {location}: {why}"),
+            }
+        }
+
+        if let Some(cache_path) = cache_path.filter(|_| cache_changed) {
+            if let Err(why) = cache.write(cache_path) {
+                eprintln!("Failed to write texture colour cache {cache_path:?}: {why}");
+            }
+        }
+
+        TextureCatalog { colors, images }
+    }
+
+    /// The decoded textures asked for when loading, leaving none behind
+    pub fn take_images(&mut self) -> HashMap<u32, RgbImage> {
+        std::mem::take(&mut self.images)
+    }
+
+    pub fn color(&self, texture_id: u32, summary: ColorSummary) -> Option<Rgb<u8>> {
+        self.colors
+            .get(&texture_id)
+            .map(|colors| colors.get(summary))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &TextureColors)> {
+        self.colors
+            .iter()
+            .map(|(&texture_id, colors)| (texture_id, colors))
+    }
+}
+
+impl ColorCache {
+    fn read(path: &Path) -> Self {
+        fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the cache through a temporary file, so that runs sharing it
+    /// never read it half written
+    fn write(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Returns the colours of the texture at `location`, decoding it only if
+    /// it is not cached or `keep_image` asks for its image. A texture whose
+    /// mtime or size changed but whose contents hash the same is not decoded
+    /// for its colours again. Zipped textures use the mtime and size of the
+    /// archive on disk.
+    fn lookup_or_insert(
+        &mut self,
+        resolver: &TextureResolver,
+        location: &TextureLocation,
+        keep_image: bool,
+    ) -> Result<Lookup, Box<dyn std::error::Error>> {
+        let key = cache_key(location);
+        let metadata = fs::metadata(location.file_path())?;
+        let size = metadata.len();
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+
+        let cached = self
+            .textures
+            .get(&key)
+            .filter(|entry| entry.mtime == mtime && entry.size == size)
+            .map(|entry| entry.colors);
+        if let (Some(colors), false) = (cached, keep_image) {
+            return Ok(Lookup {
+                colors,
+                changed: false,
+                image: None,
+            });
+        }
+
+        let data = resolver.read(location)?;
+        let decode = || -> Result<RgbImage, image::ImageError> {
+            Ok(image::load_from_memory(&data)?.to_rgb8())
+        };
+        let image = keep_image.then(decode).transpose()?;
+        if let Some(colors) = cached {
+            return Ok(Lookup {
+                colors,
+                changed: false,
+                image,
+            });
+        }
+
+        let hash = fnv1a(&data);
+        if let Some(entry) = self.textures.get_mut(&key) {
+            if entry.hash == hash {
+                entry.mtime = mtime;
+                entry.size = size;
+                return Ok(Lookup {
+                    colors: entry.colors,
+                    changed: true,
+                    image,
+                });
+            }
+        }
+
+        let img = match image {
+            Some(img) => img,
+            None => decode()?,
+        };
+        let colors = TextureColors::from_image(&img);
+        self.textures.insert(
+            key,
+            CacheEntry {
+                mtime,
+                size,
+                hash,
+                colors,
+            },
+        );
+
+        Ok(Lookup {
+            colors,
+            changed: true,
+            image: keep_image.then_some(img),
+        })
+    }
+}
+
+/// The colour cache in the user's cache directory: `$XDG_CACHE_HOME` or
+/// `~/.cache` on Unix, `~/Library/Caches` on macOS and `%LOCALAPPDATA%` on
+/// Windows. `None` when none of them is set.
+pub fn default_cache_path() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    let dir = if cfg!(windows) {
+        PathBuf::from(var("LOCALAPPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(var("HOME")?).join("Library/Caches")
+    } else {
+        var("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| Some(PathBuf::from(var("HOME")?).join(".cache")))?
+    };
+    Some(dir.join("elev2png").join(CACHE_FILE_NAME))
+}
+
+/// Textures are keyed by the absolute path of their file, followed by the
+/// entries of the archives they are in, as one cache serves every source
+fn cache_key(location: &TextureLocation) -> String {
+    let path = location.file_path();
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut key = path.to_string_lossy().into_owned();
+    if let TextureLocation::Zip { entries, .. } = location {
+        for entry in entries {
+            key.push('!');
+            key.push_str(entry);
+        }
+    }
+    key
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is stable between builds
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn average_color(img: &RgbImage) -> [u8; 3] {
+    let (r, g, b) = img.pixels().fold((0u64, 0u64, 0u64), |acc, pixel| {
+        (
+            acc.0 + pixel[0] as u64,
+            acc.1 + pixel[1] as u64,
+            acc.2 + pixel[2] as u64,
+        )
+    });
+    let pixel_count = (img.width() * img.height()).max(1) as u64;
+    [
+        (r / pixel_count) as u8,
+        (g / pixel_count) as u8,
+        (b / pixel_count) as u8,
+    ]
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let c = value as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u8 {
+    let c = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+fn linear_average_color(img: &RgbImage) -> [u8; 3] {
+    let lut: Vec<f64> = (0..=255).map(srgb_to_linear).collect();
+    let mut sums = [0.0f64; 3];
+    for pixel in img.pixels() {
+        for (sum, &value) in sums.iter_mut().zip(pixel.0.iter()) {
+            *sum += lut[value as usize];
+        }
+    }
+    let pixel_count = (img.width() * img.height()).max(1) as f64;
+    sums.map(|sum| linear_to_srgb(sum / pixel_count))
+}
+
+fn median_color(img: &RgbImage) -> [u8; 3] {
+    let mut histograms = [[0u32; 256]; 3];
+    for pixel in img.pixels() {
+        for (histogram, &value) in histograms.iter_mut().zip(pixel.0.iter()) {
+            histogram[value as usize] += 1;
+        }
+    }
+
+    let half = (img.width() * img.height()).div_ceil(2);
+    histograms.map(|histogram| {
+        let mut seen = 0;
+        histogram
+            .iter()
+            .position(|&count| {
+                seen += count;
+                seen >= half
+            })
+            .unwrap_or(0) as u8
+    })
+}
+
+fn dominant_color(img: &RgbImage) -> [u8; 3] {
+    // Bucket colours by their top 4 bits per channel
+    let bucket = |pixel: &Rgb<u8>| {
+        ((pixel[0] as usize >> 4) << 8) | ((pixel[1] as usize >> 4) << 4) | (pixel[2] as usize >> 4)
+    };
+
+    let mut counts = vec![0u32; 4096];
+    for pixel in img.pixels() {
+        counts[bucket(pixel)] += 1;
+    }
+
+    let Some(dominant) = (0..counts.len()).max_by_key(|&i| counts[i]) else {
+        return [0, 0, 0];
+    };
+
+    let mut sums = [0u64; 3];
+    for pixel in img.pixels().filter(|pixel| bucket(pixel) == dominant) {
+        for (sum, &value) in sums.iter_mut().zip(pixel.0.iter()) {
+            *sum += value as u64;
+        }
+    }
+    let count = u64::from(counts[dominant]).max(1);
+    sums.map(|sum| (sum / count) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    fn write_texture(path: &Path, color: [u8; 3], size: u32) {
+        RgbImage::from_pixel(size, size, Rgb(color))
+            .save(path)
+            .unwrap();
+    }
+
+    #[test]
+    fn cache_notices_edited_textures_with_the_same_mtime() {
+        let dir = std::env::temp_dir().join(format!("elev2png-catalog-{}", std::process::id()));
+        let textures = dir.join("textures");
+        fs::create_dir_all(&textures).unwrap();
+        let cache_path = dir.join("cache").join("colors.json");
+        let texture = textures.join("terrain3.png");
+        let resolver = TextureResolver::new(&textures);
+        let load = || TextureCatalog::load(&resolver, [3], Some(&cache_path), &BTreeSet::new());
+
+        write_texture(&texture, [10, 20, 30], 4);
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        File::options()
+            .write(true)
+            .open(&texture)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        assert_eq!(
+            load().color(3, ColorSummary::Average),
+            Some(Rgb([10, 20, 30]))
+        );
+        assert!(cache_path.is_file());
+
+        // Replaced by a texture of another size, as if copied with its mtime
+        write_texture(&texture, [200, 100, 0], 8);
+        File::options()
+            .write(true)
+            .open(&texture)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        assert_eq!(
+            load().color(3, ColorSummary::Average),
+            Some(Rgb([200, 100, 0]))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn summaries() {
+        let mut img = RgbImage::from_pixel(4, 1, Rgb([0, 0, 0]));
+        img.put_pixel(3, 0, Rgb([255, 255, 255]));
+        let colors = TextureColors::from_image(&img);
+
+        assert_eq!(colors.average, [63, 63, 63]);
+        assert_eq!(colors.linear, [137, 137, 137]);
+        assert_eq!(colors.median, [0, 0, 0]);
+        assert_eq!(colors.dominant, [0, 0, 0]);
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use elev::{Orientation, Rotation};
+use image::imageops::{self, FilterType};
+use image::{Rgb, RgbImage};
+
+/// Textures scaled to the size at which each cell is drawn, so that a cell
+/// can be rendered as a patch of its actual texture.
+pub struct TexturePatches {
+    size: u32,
+    patches: HashMap<u32, RgbImage>,
+}
+
+impl TexturePatches {
+    /// Scales each decoded texture to `size`×`size` pixels
+    pub fn new(images: HashMap<u32, RgbImage>, size: u32) -> Self {
+        let patches = images
+            .into_iter()
+            .map(|(texture_id, img)| {
+                let patch = if size < img.width() || size < img.height() {
+                    // Area averaging, so shrinking doesn't alias
+                    imageops::thumbnail(&img, size, size)
+                } else {
+                    imageops::resize(&img, size, size, FilterType::CatmullRom)
+                };
+                (texture_id, patch)
+            })
+            .collect();
+
+        TexturePatches { size, patches }
+    }
+
+    /// The colour at (`x`, `z`) of the patch drawn for a cell, in patch
+    /// coordinates along which world x and z both decrease, matching the
+    /// texture mapping used by elev3d. See [`patch_coordinates`].
+    pub fn pixel(&self, texture_id: u32, rotation: Rotation, x: u32, z: u32) -> Option<Rgb<u8>> {
+        let patch = self.patches.get(&texture_id)?;
+        let last = self.size - 1;
+        let (u, v) = match rotation {
+            Rotation::R0 => (x, z),
+            Rotation::R1 => (z, last - x),
+            Rotation::R2 => (last - x, last - z),
+            Rotation::R3 => (last - z, x),
+        };
+        Some(*patch.get_pixel(u, v))
+    }
+}
+
+/// Converts pixel (`x`, `y`) of a cell drawn `size` pixels square in an image
+/// laid out by `orientation` into patch coordinates, in which world x and z
+/// both decrease as they do across a north-up, unmirrored map
+pub fn patch_coordinates(orientation: Orientation, x: u32, y: u32, size: u32) -> (u32, u32) {
+    let last = size - 1;
+    let (right, down) = (orientation.right(), orientation.down());
+    // Exactly one of the image axes runs along each world axis, one way or
+    // the other
+    let along = |right: i32, down: i32| match (right, down) {
+        (-1, _) => x,
+        (1, _) => last - x,
+        (_, -1) => y,
+        _ => last - y,
+    };
+    (along(right.0, down.0), along(right.1, down.1))
+}
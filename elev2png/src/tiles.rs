@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use elev::ElevMap;
+use image::buffer::ConvertBuffer;
+use image::{RgbImage, Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::overlay::ImageLayout;
+
+/// Width and height in pixels of every tile
+pub const TILE_SIZE: u32 = 256;
+
+const VIEWER: &str = include_str!("viewer.html");
+
+/// What the viewer needs to know about the pyramid, written into its page
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PyramidInfo {
+    pub tile_size: u32,
+    /// Zoom level at which one image pixel is one tile pixel
+    pub max_zoom: u32,
+    pub width: u32,
+    pub height: u32,
+    pub cell_size: f64,
+    /// World cell coordinates of the cell drawn at pixel (0, 0)
+    pub origin_world_x: i32,
+    pub origin_world_z: i32,
+    /// Change in world cell coordinates `(x, z)` per pixel right and down
+    pub world_step_right: (i32, i32),
+    pub world_step_down: (i32, i32),
+    pub orientation: String,
+}
+
+/// Cuts a rendered map into a `z/x/y.png` pyramid of tiles in `output`,
+/// halving the resolution at each zoom level below the native one, and
+/// writes an `index.html` viewer beside them. Tiles without any page of the
+/// map are skipped. Transparent tiles keep their alpha channel, and others
+/// are padded with black.
+pub fn write_tiles(
+    img: &RgbaImage,
+    elev_map: &ElevMap,
+    layout: ImageLayout,
+    transparent: bool,
+    output: &Path,
+) -> Result<(PyramidInfo, usize), Box<dyn Error>> {
+    let background = if transparent {
+        Rgba([0, 0, 0, 0])
+    } else {
+        Rgba([0, 0, 0, 255])
+    };
+
+    let pages: HashSet<(i32, i32)> = elev_map.iter_pages().map(|(&coords, _)| coords).collect();
+
+    // The pages under a rectangle of pixels, found from the cells at two of
+    // its opposite corners
+    let page_ranges = |left: u32, top: u32, right: u32, bottom: u32| {
+        let cell = |pixel_x: u32, pixel_y: u32| {
+            let to_cell = |pixel: u32| (pixel as f64 / layout.cell_size) as u32;
+            layout.extent.world_cell(to_cell(pixel_x), to_cell(pixel_y))
+        };
+        let (first, last) = (cell(left, top), cell(right, bottom));
+        let pages = |a: i32, b: i32| a.min(b).div_euclid(128)..=a.max(b).div_euclid(128);
+        (pages(first.0, last.0), pages(first.1, last.1))
+    };
+
+    let longest_side = img.width().max(img.height());
+    let max_zoom = longest_side.div_ceil(TILE_SIZE).next_power_of_two().ilog2();
+
+    let mut tiles = HashMap::new();
+    for tile_y in 0..img.height().div_ceil(TILE_SIZE) {
+        for tile_x in 0..img.width().div_ceil(TILE_SIZE) {
+            let (left, top) = (tile_x * TILE_SIZE, tile_y * TILE_SIZE);
+            let right = (left + TILE_SIZE).min(img.width()) - 1;
+            let bottom = (top + TILE_SIZE).min(img.height()) - 1;
+            let (page_xs, page_zs) = page_ranges(left, top, right, bottom);
+            let has_page = page_xs.into_iter().any(|page_x| {
+                page_zs
+                    .clone()
+                    .any(|page_z| pages.contains(&(page_x, page_z)))
+            });
+            if has_page {
+                tiles.insert((tile_x, tile_y), crop_tile(img, left, top, background));
+            }
+        }
+    }
+
+    let mut tile_count = 0;
+    for zoom in (0..=max_zoom).rev() {
+        for (&(tile_x, tile_y), tile) in &tiles {
+            let dir = output.join(zoom.to_string()).join(tile_x.to_string());
+            fs::create_dir_all(&dir)?;
+            let path = dir.join(format!("{tile_y}.png"));
+            if transparent {
+                tile.save(path)?;
+            } else {
+                ConvertBuffer::<RgbImage>::convert(tile).save(path)?;
+            }
+            tile_count += 1;
+        }
+        tiles = downsample(&tiles, background);
+    }
+
+    let info = PyramidInfo {
+        tile_size: TILE_SIZE,
+        max_zoom,
+        width: img.width(),
+        height: img.height(),
+        cell_size: layout.cell_size,
+        origin_world_x: layout.extent.origin_world_x,
+        origin_world_z: layout.extent.origin_world_z,
+        world_step_right: layout.extent.orientation.right(),
+        world_step_down: layout.extent.orientation.down(),
+        orientation: layout.extent.orientation.to_string(),
+    };
+    fs::write(
+        output.join("index.html"),
+        VIEWER.replace("/*CONFIG*/", &serde_json::to_string(&info)?),
+    )?;
+
+    Ok((info, tile_count))
+}
+
+/// The tile whose top left pixel is (`left`, `top`), padded with
+/// `background` past the edges of the image
+fn crop_tile(img: &RgbaImage, left: u32, top: u32, background: Rgba<u8>) -> RgbaImage {
+    RgbaImage::from_fn(TILE_SIZE, TILE_SIZE, |x, y| {
+        img.get_pixel_checked(left + x, top + y)
+            .copied()
+            .unwrap_or(background)
+    })
+}
+
+/// Builds the next zoom level out, in which each tile averages the 2×2
+/// pixels of the four tiles it covers, weighting colours by their opacity
+fn downsample(
+    tiles: &HashMap<(u32, u32), RgbaImage>,
+    background: Rgba<u8>,
+) -> HashMap<(u32, u32), RgbaImage> {
+    let parents: HashSet<(u32, u32)> = tiles.keys().map(|&(x, y)| (x / 2, y / 2)).collect();
+
+    parents
+        .into_iter()
+        .map(|(parent_x, parent_y)| {
+            let half = TILE_SIZE / 2;
+            let tile = RgbaImage::from_fn(TILE_SIZE, TILE_SIZE, |x, y| {
+                let child = (parent_x * 2 + x / half, parent_y * 2 + y / half);
+                let Some(child) = tiles.get(&child) else {
+                    return background;
+                };
+                let (child_x, child_y) = ((x % half) * 2, (y % half) * 2);
+                let mut sums = [0u32; 3];
+                let mut alpha_sum = 0;
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let pixel = child.get_pixel(child_x + dx, child_y + dy);
+                    let alpha = u32::from(pixel[3]);
+                    for (sum, &value) in sums.iter_mut().zip(pixel.0.iter()) {
+                        *sum += u32::from(value) * alpha;
+                    }
+                    alpha_sum += alpha;
+                }
+                if alpha_sum == 0 {
+                    return Rgba([0, 0, 0, 0]);
+                }
+                let [r, g, b] = sums.map(|sum| ((sum + alpha_sum / 2) / alpha_sum) as u8);
+                Rgba([r, g, b, ((alpha_sum + 2) / 4) as u8])
+            });
+            ((parent_x, parent_y), tile)
+        })
+        .collect()
+}
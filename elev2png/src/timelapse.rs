@@ -0,0 +1,227 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use color_quant::NeuQuant;
+use elev::{ElevDump, ElevMap};
+use image::buffer::ConvertBuffer;
+use image::{RgbImage, RgbaImage};
+use rayon::prelude::*;
+
+use crate::extent::union_bounds;
+use crate::metadata;
+
+/// Most pixels sampled from all frames to choose the colours of a GIF
+const MAX_PALETTE_SAMPLES: usize = 1 << 20;
+
+/// One elevdump of a timelapse
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub path: PathBuf,
+    /// Given in the list, or else the dump's file name up to its first dot
+    pub caption: String,
+}
+
+impl Frame {
+    pub fn load(&self) -> Result<ElevMap, Box<dyn Error>> {
+        let dump = ElevDump::from_file_or_stdin(&self.path)
+            .map_err(|why| format!("Failed importing {:?}: {why:?}", self.path))?;
+        Ok(ElevMap::from(&dump))
+    }
+}
+
+/// Reads a list of elevdumps, one per line in the order to show them, each
+/// optionally followed by a tab and its caption. Blank lines and lines
+/// starting with '#' are skipped, and relative paths are taken from the
+/// list's directory.
+pub fn read_list(path: &Path) -> Result<Vec<Frame>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let frames: Vec<Frame> = contents
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (file, caption) = match line.split_once('\t') {
+                Some((file, caption)) => (file.trim(), Some(caption.trim())),
+                None => (line.trim(), None),
+            };
+            let path = base.join(file);
+            let caption = caption.map_or_else(
+                || {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    name.split('.').next().unwrap_or_default().to_string()
+                },
+                str::to_string,
+            );
+            Frame { path, caption }
+        })
+        .collect();
+
+    if frames.is_empty() {
+        return Err(format!("{path:?} lists no elevdumps").into());
+    }
+    Ok(frames)
+}
+
+/// What the frames of a timelapse have in common, so that they can share
+/// their bounds and colours
+#[derive(Debug)]
+pub struct Survey {
+    /// Page bounds covering every frame, as given by [`ElevMap::get_bounds`]
+    pub bounds: (i32, i32, i32, i32),
+    pub texture_ids: BTreeSet<u32>,
+    /// The lowest and highest cell heights of any frame
+    pub height_range: (i32, i32),
+}
+
+/// Loads each frame in turn, keeping only what they share
+pub fn survey(frames: &[Frame]) -> Result<Survey, Box<dyn Error>> {
+    let mut survey = Survey {
+        bounds: (i32::MAX, i32::MAX, i32::MIN, i32::MIN),
+        texture_ids: BTreeSet::new(),
+        height_range: (i32::MAX, i32::MIN),
+    };
+
+    for frame in frames {
+        let map = frame.load()?;
+        survey.bounds = union_bounds(survey.bounds, map.get_bounds());
+        survey.texture_ids.extend(map.texture_ids());
+        for (_, page) in map.iter_pages() {
+            for (_, _, cell) in page.iter_cells().filter(|&(x, z, _)| page.is_defined(x, z)) {
+                survey.height_range.0 = survey.height_range.0.min(cell.height);
+                survey.height_range.1 = survey.height_range.1.max(cell.height);
+            }
+        }
+    }
+    Ok(survey)
+}
+
+/// Draws each frame with `render` and writes them, `delay_ms` apart, as an
+/// animated GIF or PNG for ".gif", ".png" and ".apng" outputs, or otherwise
+/// as numbered PNGs in the `output` directory. Only the frames of a GIF are
+/// held in memory together. Returns the number of frames written.
+pub fn write(
+    frames: &[Frame],
+    render: impl FnMut(&ElevMap, &Frame) -> RgbaImage,
+    output: &Path,
+    delay_ms: u16,
+    transparent: bool,
+) -> Result<usize, Box<dyn Error>> {
+    let extension = output
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("gif") => write_gif(frames, render, output, delay_ms),
+        Some("png" | "apng") => write_apng(frames, render, output, delay_ms, transparent),
+        _ => write_sequence(frames, render, output, transparent),
+    }
+}
+
+fn write_sequence(
+    frames: &[Frame],
+    mut render: impl FnMut(&ElevMap, &Frame) -> RgbaImage,
+    output: &Path,
+    transparent: bool,
+) -> Result<usize, Box<dyn Error>> {
+    fs::create_dir_all(output)?;
+    let digits = frames.len().to_string().len().max(4);
+    for (index, frame) in frames.iter().enumerate() {
+        let img = render(&frame.load()?, frame);
+        let file = File::create(output.join(format!("{:0digits$}.png", index + 1)))?;
+        metadata::write_png(&img, transparent, BufWriter::new(file), Vec::new())?;
+    }
+    Ok(frames.len())
+}
+
+fn write_apng(
+    frames: &[Frame],
+    mut render: impl FnMut(&ElevMap, &Frame) -> RgbaImage,
+    output: &Path,
+    delay_ms: u16,
+    transparent: bool,
+) -> Result<usize, Box<dyn Error>> {
+    // The encoder needs the size of the frames, known once one is drawn
+    let mut writer = None;
+    for frame in frames {
+        let img = render(&frame.load()?, frame);
+        let writer = match &mut writer {
+            Some(writer) => writer,
+            None => {
+                let file = BufWriter::new(File::create(output)?);
+                let mut encoder = metadata::png_encoder(
+                    file,
+                    img.width(),
+                    img.height(),
+                    transparent,
+                    Vec::new(),
+                )?;
+                // Zero plays loops forever
+                encoder.set_animated(frames.len() as u32, 0)?;
+                encoder.set_frame_delay(delay_ms, 1000)?;
+                writer.insert(encoder.write_header()?)
+            }
+        };
+        if transparent {
+            writer.write_image_data(img.as_raw())?;
+        } else {
+            let rgb: RgbImage = img.convert();
+            writer.write_image_data(rgb.as_raw())?;
+        }
+    }
+    if let Some(writer) = writer {
+        writer.finish()?;
+    }
+    Ok(frames.len())
+}
+
+fn write_gif(
+    frames: &[Frame],
+    mut render: impl FnMut(&ElevMap, &Frame) -> RgbaImage,
+    output: &Path,
+    delay_ms: u16,
+) -> Result<usize, Box<dyn Error>> {
+    let images = frames
+        .iter()
+        .map(|frame| Ok(render(&frame.load()?, frame)))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let (width, height) = images[0].dimensions();
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err("GIFs can be at most 65535 pixels on a side".into());
+    };
+
+    // One palette for every frame, so that colours don't flicker between them
+    let pixel_count: usize = images.iter().map(|img| img.as_raw().len() / 4).sum();
+    let step = (pixel_count / MAX_PALETTE_SAMPLES).max(1);
+    let samples: Vec<u8> = images
+        .iter()
+        .flat_map(|img| img.as_raw().chunks_exact(4).step_by(step))
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+        .collect();
+    let quantizer = NeuQuant::new(10, 256, &samples);
+
+    let file = BufWriter::new(File::create(output)?);
+    let mut encoder = gif::Encoder::new(file, width, height, &quantizer.color_map_rgb())?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    for img in &images {
+        let indices: Vec<u8> = img
+            .as_raw()
+            .par_chunks_exact(4)
+            .map(|pixel| quantizer.index_of(&[pixel[0], pixel[1], pixel[2], 255]) as u8)
+            .collect();
+        encoder.write_frame(&gif::Frame {
+            width,
+            height,
+            // In hundredths of a second
+            delay: delay_ms / 10,
+            buffer: Cow::Owned(indices),
+            ..Default::default()
+        })?;
+    }
+    Ok(frames.len())
+}
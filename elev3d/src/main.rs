@@ -10,22 +10,30 @@ pub fn run() {
     viewer::run(ViewerSettings {
         elevdump: "mion.txt".into(),
         texture_dir: PathBuf::default(),
+        texture_pattern: TextureResolver::DEFAULT_PATTERN.to_string(),
         water_level: Some(1850),
     });
 }
 
 use clap::Parser;
+use elev::TextureResolver;
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(version, about)]
 struct Args {
-    /// The AW elevdump to view
+    /// The AW elevdump to view, or "-" to read it from stdin
     elevdump: PathBuf,
 
-    /// Directory containing textures in the form of "terrain#.jpg"
+    /// Directory or zip archive containing textures in the form of
+    /// "terrain#.jpg", or zipped as "terrain#.zip"
     texture_dir: PathBuf,
 
+    /// Texture file name pattern, in which "{id}" is replaced by the texture
+    /// id. Without an extension, jpg, jpeg, png, bmp and dds are tried.
+    #[arg(long, default_value = TextureResolver::DEFAULT_PATTERN)]
+    texture_pattern: String,
+
     /// Water level
     #[arg(long)]
     water_level: Option<i32>,
@@ -38,6 +46,7 @@ fn main() {
         texture_dir: std::env::current_dir()
             .unwrap_or_default()
             .join(args.texture_dir),
+        texture_pattern: args.texture_pattern,
         water_level: args.water_level,
     });
 }
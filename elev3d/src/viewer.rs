@@ -1,8 +1,10 @@
 use std::path::PathBuf;
 
 use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::texture::{CompressedImageFormats, ImageSampler, ImageType};
 use bevy_flycam::prelude::*;
-use elev::{ElevDump, ElevMap};
+use elev::{ElevDump, ElevMap, TextureLocation, TextureResolver};
 use terrain_mesh::create_terrain_meshes;
 
 use crate::terrain_mesh;
@@ -11,6 +13,7 @@ use crate::terrain_mesh;
 pub struct ViewerSettings {
     pub elevdump: PathBuf,
     pub texture_dir: PathBuf,
+    pub texture_pattern: String,
     pub water_level: Option<i32>,
 }
 
@@ -28,24 +31,54 @@ fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
     asset_server: Res<AssetServer>,
     settings: Res<ViewerSettings>,
 ) {
     // println!("Setup");
-    let elev_map = ElevMap::from(&ElevDump::from_file(&settings.elevdump).unwrap());
+    let elev_map = ElevMap::from(&ElevDump::from_file_or_stdin(&settings.elevdump).unwrap());
     // println!("elev_map made");
 
     // Create the terrain mesh
     let terrain_meshes = create_terrain_meshes(&elev_map);
     // println!("terrain_meshes made");
 
+    let resolver =
+        TextureResolver::new(&settings.texture_dir).with_pattern(&settings.texture_pattern);
+    #[cfg(not(target_arch = "wasm32"))]
+    let textures = resolver.resolve_all(terrain_meshes.keys().copied());
+    // There is no filesystem to search on the web, so request the default names
+    #[cfg(target_arch = "wasm32")]
+    let textures = elev::ResolvedTextures {
+        found: terrain_meshes
+            .keys()
+            .map(|&texture_id| {
+                let path = resolver.default_path(texture_id);
+                (texture_id, TextureLocation::File(path))
+            })
+            .collect(),
+        missing: Default::default(),
+    };
+    if !textures.missing.is_empty() {
+        eprintln!(
+            "No texture found in {:?} for ids: {:?}",
+            resolver.source(),
+            textures.missing
+        );
+    }
+
     for (texture_id, mesh) in terrain_meshes {
-        let texture_path = settings
-            .texture_dir
-            .join(format!("terrain{texture_id}.jpg"));
-        let texture_handle = asset_server.load(texture_path);
+        let texture_handle = textures
+            .found
+            .get(&texture_id)
+            .and_then(|location| match location {
+                TextureLocation::File(path) => Some(asset_server.load(path.clone())),
+                TextureLocation::Zip { .. } => {
+                    load_zipped_texture(&resolver, location, &mut images)
+                }
+            });
         let material = materials.add(StandardMaterial {
-            base_color_texture: Some(texture_handle),
+            base_color_texture: texture_handle,
             metallic: 0.0,
             perceptual_roughness: 1.0, // Lower values make it appear more reflective
             reflectance: 0.1,
@@ -106,3 +139,39 @@ fn setup(
         });
     }
 }
+
+/// The asset server can only load files, so textures inside zip archives are
+/// decoded here instead
+fn load_zipped_texture(
+    resolver: &TextureResolver,
+    location: &TextureLocation,
+    images: &mut Assets<Image>,
+) -> Option<Handle<Image>> {
+    let data = match resolver.read(location) {
+        Ok(data) => data,
+        Err(why) => {
+            eprintln!("Failed to read texture {location}: {why}");
+            return None;
+        }
+    };
+
+    let image = Image::from_buffer(
+        // Only taken in debug builds, since the "dds" feature is enabled
+        #[cfg(debug_assertions)]
+        location.to_string(),
+        &data,
+        ImageType::Extension(location.extension().unwrap_or("jpg")),
+        CompressedImageFormats::NONE,
+        true,
+        ImageSampler::Default,
+        RenderAssetUsages::default(),
+    );
+
+    match image {
+        Ok(image) => Some(images.add(image)),
+        Err(why) => {
+            eprintln!("Failed to decode texture {location}: {why}");
+            None
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use std::path::Path;
+use std::process::ExitCode;
+
+use elev::ElevDump;
+
+pub fn run(input: &Path, output: &Path) -> ExitCode {
+    let elevdump = match ElevDump::from_file_or_stdin(input) {
+        Ok(e) => e,
+        Err(why) => {
+            eprintln!("Failed importing elevdump: {why:#?}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match elevdump.to_file_or_stdout(output) {
+        Ok(()) if output == Path::new("-") => ExitCode::SUCCESS,
+        Ok(()) => {
+            println!("Elevdump saved to {output:?}");
+            ExitCode::SUCCESS
+        }
+        Err(why) => {
+            eprintln!("Failed to save elevdump: {why}");
+            ExitCode::FAILURE
+        }
+    }
+}
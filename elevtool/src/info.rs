@@ -0,0 +1,103 @@
+use std::path::Path;
+use std::process::ExitCode;
+
+use elev::{ElevDump, ElevMap, ElevStats};
+
+pub fn run(elevdump: &Path, json: bool, bins: usize, pages: bool) -> ExitCode {
+    let elevdump = match ElevDump::from_file_or_stdin(elevdump) {
+        Ok(e) => e,
+        Err(why) => {
+            eprintln!("Failed importing elevdump: {why:#?}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let elev_map = ElevMap::from(&elevdump);
+    let stats = ElevStats::with_histogram_bins(&elevdump, &elev_map, bins);
+
+    if json {
+        return match serde_json::to_string_pretty(&stats) {
+            Ok(s) => {
+                println!("{s}");
+                ExitCode::SUCCESS
+            }
+            Err(why) => {
+                eprintln!("Failed serialising statistics: {why}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    print_tables(&stats, pages);
+    ExitCode::SUCCESS
+}
+
+fn print_tables(stats: &ElevStats, pages: bool) {
+    println!("Entries: {}", stats.entry_count);
+    println!("Pages:   {}", stats.page_count);
+    println!("Cells:   {}", stats.cell_count);
+
+    if let Some(heights) = &stats.heights {
+        println!();
+        println!("Heights");
+        println!("  min   {:>8}", heights.min);
+        println!("  max   {:>8}", heights.max);
+        println!("  mean  {:>10.1}", heights.mean);
+        for (percentile, height) in &heights.percentiles {
+            println!("  p{percentile:<4} {height:>8}");
+        }
+
+        println!();
+        println!("Height histogram");
+        let largest = heights.histogram.counts.iter().copied().max().unwrap_or(0);
+        for (start, count) in heights.histogram.iter_bins() {
+            let end = start + heights.histogram.bin_width - 1;
+            let bar_len = (count * 40).checked_div(largest).unwrap_or(0);
+            println!(
+                "  {start:>7} .. {end:>7} {count:>9} {}",
+                "#".repeat(bar_len)
+            );
+        }
+    }
+
+    println!();
+    println!("Textures");
+    println!(
+        "  {:>7} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "id", "cells", "R0", "R1", "R2", "R3"
+    );
+    for usage in &stats.textures {
+        let [r0, r1, r2, r3] = usage.rotations;
+        println!(
+            "  {:>7} {:>9} {r0:>9} {r1:>9} {r2:>9} {r3:>9}",
+            usage.texture_id, usage.count
+        );
+    }
+
+    println!();
+    println!("Node radii");
+    println!("  {:>7} {:>9}", "radius", "entries");
+    for (radius, count) in &stats.node_radii {
+        println!("  {radius:>7} {count:>9}");
+    }
+
+    if pages {
+        println!();
+        println!("Pages");
+        println!(
+            "  {:>7} {:>7} {:>8} {:>8} {:>10} {:>8}",
+            "x", "z", "min", "max", "mean", "textures"
+        );
+        for page in &stats.pages {
+            println!(
+                "  {:>7} {:>7} {:>8} {:>8} {:>10.1} {:>8}",
+                page.page_x,
+                page.page_z,
+                page.min_height,
+                page.max_height,
+                page.mean_height,
+                page.texture_count
+            );
+        }
+    }
+}
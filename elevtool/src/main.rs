@@ -0,0 +1,58 @@
+mod convert;
+mod info;
+
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print statistics about an elevdump
+    Info {
+        /// The AW elevdump to inspect, or "-" to read it from stdin
+        elevdump: PathBuf,
+
+        /// Print the statistics as JSON instead of tables
+        #[arg(long)]
+        json: bool,
+
+        /// Number of bins in the height histogram
+        #[arg(long, default_value_t = elev::HeightHistogram::DEFAULT_BINS)]
+        bins: usize,
+
+        /// Also list a summary of every page
+        #[arg(long)]
+        pages: bool,
+    },
+
+    /// Rewrite an elevdump, compressing or decompressing it according to the
+    /// output file extension (".gz" or ".zst")
+    Convert {
+        /// The AW elevdump to read, or "-" to read it from stdin
+        input: PathBuf,
+
+        /// File to produce, or "-" to write uncompressed text to stdout
+        output: PathBuf,
+    },
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    match args.command {
+        Command::Info {
+            elevdump,
+            json,
+            bins,
+            pages,
+        } => info::run(&elevdump, json, bins, pages),
+        Command::Convert { input, output } => convert::run(&input, &output),
+    }
+}
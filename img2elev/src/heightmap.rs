@@ -0,0 +1,157 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::DynamicImage;
+use serde::Deserialize;
+
+/// The parts of the JSON sidecar written by `elev2png --format heightmap16`
+/// needed to place a heightmap back in the world
+#[derive(Debug, Deserialize)]
+pub struct HeightmapInfo {
+    pub width: u32,
+    pub height: u32,
+    pub offset: f64,
+    pub scale: f64,
+    pub origin_world_x: i32,
+    pub origin_world_z: i32,
+    /// Change in world cell coordinates `(x, z)` per pixel right and down,
+    /// absent from sidecars of north-up heightmaps written before
+    /// orientations could be chosen
+    pub world_step_right: Option<(i32, i32)>,
+    pub world_step_down: Option<(i32, i32)>,
+    /// Pixel value of cells absent from the map, absent from sidecars written
+    /// before it was recorded
+    pub missing_value: Option<u16>,
+}
+
+impl HeightmapInfo {
+    /// Reads the sidecar next to `image`, if there is one
+    pub fn for_image(image: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        let path = sidecar_path(image);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+}
+
+/// A greyscale image as raw samples, row by row
+#[derive(Debug)]
+pub struct Samples {
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<u16>,
+}
+
+impl Samples {
+    /// Reads an 8- or 16-bit greyscale image, keeping the sample values as
+    /// they are rather than stretching 8-bit values to 16 bits. ".r16" and
+    /// ".raw" files hold little-endian 16-bit samples and need their width,
+    /// and their size is checked against `raw_height` if it is known.
+    pub fn from_file(
+        path: &Path,
+        raw_width: Option<u32>,
+        raw_height: Option<u32>,
+    ) -> Result<Self, Box<dyn Error>> {
+        if let Some("r16" | "raw") = path.extension().and_then(|ext| ext.to_str()) {
+            let Some(width) = raw_width.filter(|&width| width > 0) else {
+                return Err(format!("The width of raw heightmap {path:?} is unknown").into());
+            };
+            return Ok(Self::from_raw(&fs::read(path)?, width, raw_height)?);
+        }
+
+        let img = image::open(path)?;
+        if img.color().has_color() {
+            eprintln!("{path:?} is not greyscale, so its luminance is used");
+        }
+        let (width, height) = (img.width(), img.height());
+        let values = match img {
+            DynamicImage::ImageLuma16(img) => img.into_raw(),
+            img if img.color().bits_per_pixel() / u16::from(img.color().channel_count()) > 8 => {
+                img.to_luma16().into_raw()
+            }
+            img => img
+                .to_luma8()
+                .into_raw()
+                .into_iter()
+                .map(u16::from)
+                .collect(),
+        };
+
+        Ok(Samples {
+            width,
+            height,
+            values,
+        })
+    }
+
+    /// Splits little-endian 16-bit samples into rows of `width`, which must
+    /// number `height` if it is given
+    fn from_raw(data: &[u8], width: u32, height: Option<u32>) -> Result<Self, String> {
+        if !data.len().is_multiple_of(2) {
+            return Err(format!(
+                "{} bytes is not a whole number of 16-bit samples",
+                data.len()
+            ));
+        }
+        let values: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        if !values.len().is_multiple_of(width as usize) {
+            return Err(format!(
+                "{} samples do not make whole rows of {width}",
+                values.len()
+            ));
+        }
+        let rows = values.len() / width as usize;
+        match height {
+            Some(height) if rows != height as usize => Err(format!(
+                "{} samples make {rows} rows of {width} rather than {height}",
+                values.len()
+            )),
+            _ => Ok(Samples {
+                width,
+                height: rows as u32,
+                values,
+            }),
+        }
+    }
+}
+
+fn sidecar_path(image: &Path) -> PathBuf {
+    let mut path = image.as_os_str().to_owned();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_raw_samples_row_by_row() {
+        let data = [1, 0, 2, 0, 0, 1, 4, 0, 5, 0, 6, 0];
+        let samples = Samples::from_raw(&data, 3, Some(2)).unwrap();
+        assert_eq!((samples.width, samples.height), (3, 2));
+        assert_eq!(samples.values, [1, 2, 256, 4, 5, 6]);
+
+        let samples = Samples::from_raw(&data, 2, None).unwrap();
+        assert_eq!((samples.width, samples.height), (2, 3));
+    }
+
+    #[test]
+    fn rejects_raw_samples_of_the_wrong_size() {
+        let data = [0; 12];
+        assert!(Samples::from_raw(&data[..11], 3, None)
+            .unwrap_err()
+            .contains("16-bit"));
+        assert!(Samples::from_raw(&data, 4, None)
+            .unwrap_err()
+            .contains("whole rows"));
+        assert!(Samples::from_raw(&data, 3, Some(3))
+            .unwrap_err()
+            .contains("rather than 3"));
+    }
+}
@@ -0,0 +1,184 @@
+mod heightmap;
+
+use clap::Parser;
+use elev::{Direction, ElevCell, ElevDump, ElevMap, Orientation, Rotation};
+use heightmap::{HeightmapInfo, Samples};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// 8- or 16-bit greyscale heightmap, or raw little-endian 16-bit samples
+    /// in a ".r16" or ".raw" file. A "<heightmap>.json" sidecar written by
+    /// elev2png provides the defaults for the options below.
+    heightmap: PathBuf,
+
+    /// Elevdump to produce, compressed if it ends in ".gz" or ".zst", or "-"
+    /// to write it to stdout
+    output: PathBuf,
+
+    /// Greyscale image of the same size whose values are the texture id of
+    /// each cell
+    #[arg(long)]
+    textures: Option<PathBuf>,
+
+    /// Texture id of every cell when there is no texture image
+    #[arg(long, default_value_t = 0)]
+    texture_id: u32,
+
+    /// Height in cm of each step of a pixel value, 1 by default
+    #[arg(long)]
+    scale: Option<f64>,
+
+    /// Height in cm of a pixel value of 0, 0 by default
+    #[arg(long, allow_negative_numbers = true)]
+    offset: Option<f64>,
+
+    /// Pixel value of cells missing from the map, which are left out of the
+    /// elevdump. None are left out by default.
+    #[arg(long)]
+    missing_value: Option<u16>,
+
+    /// World cell x of the top left pixel, by default placing the image's
+    /// eastern edge at x = 0
+    #[arg(long, allow_negative_numbers = true)]
+    origin_x: Option<i32>,
+
+    /// World cell z of the top left pixel, by default placing the image's
+    /// southern edge at z = 0
+    #[arg(long, allow_negative_numbers = true)]
+    origin_z: Option<i32>,
+
+    /// Compass direction at the top of the image, north by default. Giving
+    /// this or --mirror ignores the placement recorded in a sidecar.
+    #[arg(long)]
+    up: Option<Direction>,
+
+    /// The image is mirrored left to right, with east on the left when north
+    /// is up
+    #[arg(long)]
+    mirror: bool,
+
+    /// Width in pixels of a raw heightmap without a sidecar
+    #[arg(long)]
+    width: Option<u32>,
+
+    /// Height in pixels of a raw heightmap without a sidecar, checked against
+    /// the size of the file
+    #[arg(long)]
+    height: Option<u32>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let info = match HeightmapInfo::for_image(&args.heightmap) {
+        Ok(info) => info,
+        Err(why) => {
+            eprintln!("Failed to read heightmap sidecar: {why}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let raw_width = args.width.or(info.as_ref().map(|info| info.width));
+    let raw_height = args.height.or(info.as_ref().map(|info| info.height));
+    let heights = match Samples::from_file(&args.heightmap, raw_width, raw_height) {
+        Ok(samples) => samples,
+        Err(why) => {
+            eprintln!("Failed to read heightmap {:?}: {why}", &args.heightmap);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let textures = match &args.textures {
+        Some(path) => match Samples::from_file(path, Some(heights.width), Some(heights.height)) {
+            Ok(samples) if (samples.width, samples.height) == (heights.width, heights.height) => {
+                Some(samples)
+            }
+            Ok(samples) => {
+                eprintln!(
+                    "Texture image is {}×{} but the heightmap is {}×{}",
+                    samples.width, samples.height, heights.width, heights.height
+                );
+                return ExitCode::FAILURE;
+            }
+            Err(why) => {
+                eprintln!("Failed to read texture image {path:?}: {why}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let scale = args.scale.or(info.as_ref().map(|info| info.scale));
+    let offset = args.offset.or(info.as_ref().map(|info| info.offset));
+    let (scale, offset) = (scale.unwrap_or(1.0), offset.unwrap_or(0.0));
+    let missing_value = args
+        .missing_value
+        .or(info.as_ref().and_then(|info| info.missing_value));
+
+    let orientation = Orientation::new(args.up.unwrap_or_default(), args.mirror);
+    let placement = info
+        .as_ref()
+        .filter(|_| args.up.is_none() && !args.mirror)
+        .map(|info| {
+            let right = info.world_step_right.unwrap_or((-1, 0));
+            let down = info.world_step_down.unwrap_or((0, -1));
+            ((info.origin_world_x, info.origin_world_z), right, down)
+        });
+    let ((origin_x, origin_z), right, down) = placement.unwrap_or_else(|| {
+        // Otherwise the image covers the cells from (0, 0) north and west
+        let (cells_x, cells_z) = if orientation.right().0 != 0 {
+            (heights.width, heights.height)
+        } else {
+            (heights.height, heights.width)
+        };
+        let origin = orientation.origin((0, 0), (cells_x as i32 - 1, cells_z as i32 - 1));
+        (origin, orientation.right(), orientation.down())
+    });
+    let origin_x = args.origin_x.unwrap_or(origin_x);
+    let origin_z = args.origin_z.unwrap_or(origin_z);
+
+    let mut elev_map = ElevMap::new();
+    for (i, &value) in heights.values.iter().enumerate() {
+        if Some(value) == missing_value {
+            continue;
+        }
+        let pixel_x = (i % heights.width as usize) as i32;
+        let pixel_y = (i / heights.width as usize) as i32;
+        let texture_id = match &textures {
+            Some(textures) => u32::from(textures.values[i]),
+            None => args.texture_id,
+        };
+
+        elev_map.set_world_cell(
+            origin_x + pixel_x * right.0 + pixel_y * down.0,
+            origin_z + pixel_x * right.1 + pixel_y * down.1,
+            ElevCell {
+                texture_id,
+                rotation: Rotation::default(),
+                height: (offset + value as f64 * scale).round() as i32,
+            },
+        );
+    }
+
+    let elevdump = ElevDump::from(&elev_map);
+    match elevdump.to_file_or_stdout(&args.output) {
+        Ok(()) => {
+            if args.output.as_os_str() != "-" {
+                println!(
+                    "Elevdump with {} entries over {} pages saved to {:?}",
+                    elevdump.entries.len(),
+                    elev_map.page_count(),
+                    &args.output
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        Err(why) => {
+            eprintln!("Failed to save elevdump: {why}");
+            ExitCode::FAILURE
+        }
+    }
+}